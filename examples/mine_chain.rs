@@ -0,0 +1,69 @@
+//! Mine a small batched-extrinsics chain and print it.
+//!
+//! Every argument is one block's worth of comma separated extrinsics. For example, the following
+//! mines three blocks on top of genesis, with extrinsics `1, 2, 3`, then `4`, then none at all.
+//!
+//! ```text
+//! cargo run --example mine_chain -- 1,2,3 4 ""
+//! ```
+
+use Blockchain_from_scratch::c2_blockchain::p4_batched_extrinsics::Block;
+use Blockchain_from_scratch::c2_blockchain::p5_fork_choice::mine_extra_hard;
+use Blockchain_from_scratch::hash;
+
+/// The PoW threshold every mined block has to get its hash below. 1 in 100 hashes will do.
+pub const THRESHOLD: u64 = u64::MAX / 100;
+
+/// Parse one argument into a batch of extrinsics.
+pub fn parse_batch(arg: &str) -> Result<Vec<u64>, String> {
+    arg.split(',')
+        .map(str::trim)
+        .filter(|extrinsic| !extrinsic.is_empty())
+        .map(|extrinsic| {
+            extrinsic
+                .parse()
+                .map_err(|_| format!("`{}` is not a valid extrinsic", extrinsic))
+        })
+        .collect()
+}
+
+/// Build a genesis block and mine one child per batch of extrinsics on top of it.
+/// The returned chain includes the genesis block.
+pub fn mine_chain(batches: &[Vec<u64>], threshold: u64) -> Vec<Block> {
+    let mut chain = vec![Block::genesis()];
+    for extrinsics in batches {
+        let mut block = chain.last().unwrap().child(extrinsics.clone());
+        mine_extra_hard(&mut block, threshold);
+        chain.push(block);
+    }
+    chain
+}
+
+/// Describe a block on a single line: its height, hash and state.
+pub fn describe(block: &Block) -> String {
+    let header = block.header();
+    format!(
+        "height: {:>3}  hash: {:016x}  state: {}",
+        header.height(),
+        hash(header),
+        header.state()
+    )
+}
+
+fn main() {
+    let batches = match std::env::args()
+        .skip(1)
+        .map(|arg| parse_batch(&arg))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(batches) => batches,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    for block in mine_chain(&batches, THRESHOLD) {
+        println!("{}", describe(&block));
+    }
+}
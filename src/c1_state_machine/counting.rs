@@ -0,0 +1,94 @@
+//! Some machines, like the ATM and both currencies, quietly ignore transitions that aren't valid
+//! and stay in the state they were in. That keeps them simple, but makes it hard to tell from the
+//! outside how many transitions actually did something. A `CountingMachine` wraps any machine and
+//! counts the transitions that left its state unchanged.
+
+use super::StateMachine;
+use std::marker::PhantomData;
+
+/// A state of the wrapped machine, together with the number of transitions that were rejected
+/// on the way to it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Counted<S> {
+    /// The state of the wrapped machine.
+    pub state: S,
+    /// How many transitions left the wrapped machine's state unchanged.
+    pub rejected: usize,
+}
+
+impl<S> Counted<S> {
+    /// Start counting from the given state, with nothing rejected yet.
+    pub fn new(state: S) -> Self {
+        Self { state, rejected: 0 }
+    }
+}
+
+/// Runs the machine `M`, and counts every transition whose resulting state equals its starting
+/// state as rejected. Transitions that are valid but legitimately change nothing, like a no-op,
+/// are counted too, because from the outside they can't be told apart.
+pub struct CountingMachine<M: StateMachine>(PhantomData<M>);
+
+impl<M: StateMachine> StateMachine for CountingMachine<M>
+where
+    M::State: PartialEq,
+{
+    type State = Counted<M::State>;
+    type Transition = M::Transition;
+
+    fn next_state(starting_state: &Self::State, transition: &Self::Transition) -> Self::State {
+        let state = M::next_state(&starting_state.state, transition);
+        let rejected = if state == starting_state.state {
+            starting_state.rejected + 1
+        } else {
+            starting_state.rejected
+        };
+        Counted { state, rejected }
+    }
+}
+
+#[cfg(test)]
+use super::p4_accounted_currency::{AccountedCurrency, AccountingTransaction, AssetId};
+#[cfg(test)]
+use super::User;
+#[cfg(test)]
+use std::collections::HashMap;
+
+#[cfg(test)]
+#[test]
+fn sm_counting_counts_rejected_transfers() {
+    let start = Counted::new(HashMap::from([((User::Alice, AssetId(0)), 100)]));
+    let transfer = |sender, receiver, amount| AccountingTransaction::Transfer {
+        sender,
+        receiver,
+        amount,
+        asset: AssetId(0),
+    };
+    let transactions = [
+        transfer(User::Alice, User::Bob, 40),
+        // Bob only has 40.
+        transfer(User::Bob, User::Charlie, 50),
+        transfer(User::Bob, User::Charlie, 10),
+        // Sending nothing changes nothing.
+        transfer(User::Charlie, User::Alice, 0),
+        // Charlie has no account in this asset.
+        AccountingTransaction::Burn {
+            burner: User::Charlie,
+            amount: 10,
+            asset: AssetId(1),
+        },
+    ];
+
+    let end = transactions.iter().fold(start, |state, transaction| {
+        CountingMachine::<AccountedCurrency>::next_state(&state, transaction)
+    });
+
+    assert_eq!(end.rejected, 3);
+    assert_eq!(
+        end.state,
+        HashMap::from([
+            ((User::Alice, AssetId(0)), 60),
+            ((User::Bob, AssetId(0)), 30),
+            ((User::Charlie, AssetId(0)), 10),
+        ])
+    );
+}
@@ -1,11 +1,20 @@
 //! This module is all about modeling phenomena and systems as state machines. We begin with a few simple
 //! examples, and then proceed to build bigger and more complex state machines all implementing the same simple interface.
 
+mod counting;
 mod p1_switches;
 mod p2_laundary_machine;
 mod p3_atm;
 mod p4_accounted_currency;
 mod p5_digital_cash;
+mod p6_traffic_light;
+mod p7_vending_machine;
+mod p8_elevator;
+mod product;
+mod snapshot;
+
+use std::fmt::Debug ;
+use std::hash::Hash ;
 
 /// A state machine - Generic over the transition type 
 pub trait StateMachine {
@@ -20,9 +29,63 @@ pub trait StateMachine {
 }
 
 /// A set of play users for experimenting with the multi-user state machines.
-#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Clone, Copy)]
 pub enum User {
     Alice,
     Bob,
     Charlie,
-}
\ No newline at end of file
+}
+
+/// Identifies an account when the three play users aren't enough, for example to test with many
+/// accounts at once. Every play user has an account id of its own, so anything written for
+/// account ids works for play users too.
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Clone, Copy)]
+pub struct AccountId(pub u32) ;
+
+impl From<User> for AccountId {
+    fn from(user: User) -> Self {
+        AccountId(user as u32)
+    }
+}
+
+/// Anything that can own funds in the multi-user state machines, such as a `User` or an `AccountId`.
+pub trait Account: Copy + Eq + Hash + Debug {}
+
+impl<T: Copy + Eq + Hash + Debug> Account for T {}
+
+impl User {
+    /// All the play users, in order.
+    pub fn all() -> [User; 3] {
+        [User::Alice, User::Bob, User::Charlie]
+    }
+
+    /// Parse a user from their name, ignoring case. Returns `None` for any other name.
+    pub fn from_str(name: &str) -> Option<User> {
+        User::all().into_iter().find(|user| format!("{:?}", user).eq_ignore_ascii_case(name))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn sm_user_all() {
+    assert_eq!(User::all().len(), 3) ;
+    assert_eq!(User::all(), [User::Alice, User::Bob, User::Charlie]) ;
+}
+
+#[test]
+fn sm_user_from_str() {
+    assert_eq!(User::from_str("Alice"), Some(User::Alice)) ;
+    assert_eq!(User::from_str("bob"), Some(User::Bob)) ;
+    assert_eq!(User::from_str("CHARLIE"), Some(User::Charlie)) ;
+    assert_eq!(User::from_str("Dave"), None) ;
+
+    for user in User::all() {
+        assert_eq!(User::from_str(&format!("{:?}", user)), Some(user)) ;
+    }
+}
+
+#[test]
+fn sm_user_into_account_id() {
+    let ids: Vec<AccountId> = User::all().into_iter().map(AccountId::from).collect() ;
+    assert_eq!(ids, vec![AccountId(0), AccountId(1), AccountId(2)]) ;
+}
@@ -24,7 +24,8 @@ impl StateMachine for LightSwitch {
 pub struct WeirdSwitchMachine;
 
 /// The state is now two switches instead of one so we use a struct.
-#[derive(PartialEq, Eq, Debug)]
+/// By default, both switches are off.
+#[derive(PartialEq, Eq, Debug, Default)]
 pub struct TwoSwitches {
     first_switch: bool,
     second_switch: bool,
@@ -61,6 +62,69 @@ impl StateMachine for WeirdSwitchMachine {
     }
 }
 
+/// The weird rule from the two switch machine generalizes to any number of switches. This
+/// machine models a whole bank of switches, where turning one off may force others off too.
+pub struct SwitchBankMachine;
+
+/// Which switches are forced off whenever a switch is turned off.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CascadeMode {
+    /// Every switch after it goes off too. With two switches, this is the weird switch machine.
+    Later,
+    /// Every switch before it goes off too.
+    Earlier,
+    /// No cascade. Every switch is independent.
+    Disabled,
+}
+
+/// The state of the bank is the position of every switch, along with the cascade rule
+/// the bank is wired with.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SwitchBank {
+    switches: Vec<bool>,
+    cascade: CascadeMode,
+}
+
+impl SwitchBank {
+    /// Create a bank of the given number of switches, all off, wired with the given cascade rule.
+    pub fn new(size: usize, cascade: CascadeMode) -> Self {
+        Self {
+            switches: vec![false; size],
+            cascade,
+        }
+    }
+}
+
+/// The transition names the switch to toggle. Toggling a switch that doesn't exist does nothing.
+impl StateMachine for SwitchBankMachine {
+    type State = SwitchBank;
+    type Transition = usize;
+
+    fn next_state(starting_state: &SwitchBank, index: &usize) -> SwitchBank {
+        let mut new_state = starting_state.clone();
+        let index = *index;
+        if index >= new_state.switches.len() {
+            return new_state;
+        }
+
+        let was_on = new_state.switches[index];
+        new_state.switches[index] = !was_on;
+
+        // Only turning a switch off triggers the cascade.
+        if was_on {
+            let forced_off = match new_state.cascade {
+                CascadeMode::Later => index + 1..new_state.switches.len(),
+                CascadeMode::Earlier => 0..index,
+                CascadeMode::Disabled => 0..0,
+            };
+            for switch in &mut new_state.switches[forced_off] {
+                *switch = false;
+            }
+        }
+        new_state
+    }
+}
+
 #[cfg(test)]
 #[test]
 fn sm_1_light_switch_toggles_off() {
@@ -156,4 +220,74 @@ fn sm_1_two_switches_second_goes_off() {
             second_switch: false,
         }
     );
-}
\ No newline at end of file
+}
+
+#[test]
+fn sm_1_two_switches_default_to_off() {
+    let expected = TwoSwitches {
+        first_switch: false,
+        second_switch: false,
+    } ;
+    assert_eq!(TwoSwitches::default(), expected) ;
+}
+
+#[test]
+fn sm_1_switch_bank_turning_on_never_cascades() {
+    for cascade in [CascadeMode::Later, CascadeMode::Earlier, CascadeMode::Disabled] {
+        let bank = SwitchBank::new(3, cascade) ;
+        let end = SwitchBankMachine::next_state(&bank, &1) ;
+        assert_eq!(end.switches, vec![false, true, false]) ;
+    }
+}
+
+#[test]
+fn sm_1_switch_bank_cascade_later() {
+    let bank = SwitchBank {
+        switches: vec![true, true, true, true],
+        cascade: CascadeMode::Later,
+    } ;
+    let end = SwitchBankMachine::next_state(&bank, &1) ;
+    assert_eq!(end.switches, vec![true, false, false, false]) ;
+}
+
+#[test]
+fn sm_1_switch_bank_cascade_earlier() {
+    let bank = SwitchBank {
+        switches: vec![true, true, true, true],
+        cascade: CascadeMode::Earlier,
+    } ;
+    let end = SwitchBankMachine::next_state(&bank, &2) ;
+    assert_eq!(end.switches, vec![false, false, false, true]) ;
+}
+
+#[test]
+fn sm_1_switch_bank_cascade_disabled() {
+    let bank = SwitchBank {
+        switches: vec![true, true, true, true],
+        cascade: CascadeMode::Disabled,
+    } ;
+    let end = SwitchBankMachine::next_state(&bank, &2) ;
+    assert_eq!(end.switches, vec![true, true, false, true]) ;
+}
+
+#[test]
+fn sm_1_switch_bank_of_two_matches_weird_switches() {
+    let bank = SwitchBank {
+        switches: vec![true, true],
+        cascade: CascadeMode::Later,
+    } ;
+    let weird = TwoSwitches {
+        first_switch: true,
+        second_switch: true,
+    } ;
+
+    let bank_end = SwitchBankMachine::next_state(&bank, &0) ;
+    let weird_end = WeirdSwitchMachine::next_state(&weird, &Toggle::FirstSwitch) ;
+    assert_eq!(bank_end.switches, vec![weird_end.first_switch, weird_end.second_switch]) ;
+}
+
+#[test]
+fn sm_1_switch_bank_ignores_missing_switch() {
+    let bank = SwitchBank::new(2, CascadeMode::Later) ;
+    assert_eq!(SwitchBankMachine::next_state(&bank, &5), bank) ;
+}
@@ -3,6 +3,7 @@
 //! entered the wrong pin.
 
 use super::StateMachine;
+use std::collections::HashMap;
 
 /// The keys on the ATM keypad.
 #[derive(Hash, Debug, PartialEq, Eq, Clone)]
@@ -15,12 +16,27 @@ pub enum Key {
 }
 
 /// Something you can do to the ATM.
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Action {
     /// Swipe your card at the ATM. The attached value is the hash of the pin
-    /// that should be keyed in on the keypad next.
+    /// that should be keyed in on the keypad next. Once the machine has an account
+    /// ledger, only cards whose pin hash is registered in it are accepted.
     SwipeCard(u64),
     /// Press a key on the keypad
     PressKey(Key),
+    /// Ask for the balance of the authenticated account. Nothing is dispensed, and the card
+    /// is returned afterwards. Ignored unless a user is authenticated.
+    BalanceInquiry,
+    /// Change the pin of the authenticated account. The attached value is the hash of the
+    /// new pin. The card is returned afterwards. Ignored unless a user is authenticated.
+    ChangePin(u64),
+    /// Withdraw as much of the authenticated account's balance as the machine can pay out,
+    /// and debit it from the account. The card is returned afterwards. Ignored unless a
+    /// user is authenticated.
+    WithdrawAll,
+    /// A unit of time passes. If the user has started keying in their pin but doesn't finish
+    /// within the machine's pin timeout, the card is returned and the keys are discarded.
+    Tick,
 }
 
 /// The various states of authentication possible with the ATM.
@@ -36,12 +52,26 @@ pub enum Authentication {
     Authenticated,
 }
 
+/// What happened to the most recent withdrawal, whether keyed in or withdrawing everything.
+/// Withdrawing everything caps the amount at the withdrawal limit, so it is never `OverLimit`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum WithdrawalResult {
+    /// The enclosed amount was paid out. A fresh machine reports that nothing was dispensed.
+    Dispensed(u64),
+    /// The machine doesn't hold enough cash, or not the right bills, to pay out the amount.
+    InsufficientCash,
+    /// The amount is above the machine's per-withdrawal limit.
+    OverLimit,
+}
+
 /// The ATM. When a card is swiped, the ATM learns the correct pin's hash.
 /// It waits for you to key in your pin. You can press as many numeric keys as
 /// you like followed by enter. If the pin is incorrect, you card is returned 
 /// and the ATM automatically goes back to the main menu. If your pin is correct,
-/// the ATM awaits for you to key in the amount of money to withdraw. Withdraws
-/// are bounded only by the cash in the machine (there is no account balance).
+/// the ATM awaits for you to key in the amount of money to withdraw. Keyed-in withdraws
+/// are bounded only by the cash in the machine (the account balance isn't checked),
+/// and the amount must be payable with the bills the machine holds. Withdrawing
+/// everything is also bounded by, and debited from, the account balance.
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Atm {
     /// How much money is in the ATM.
@@ -49,7 +79,114 @@ pub struct Atm {
     /// The machine's authentication status.
     expected_pin_hash: Authentication,
     /// All the keys have been pressed since the last enter.
-    keystroke_register: Vec<Key>
+    keystroke_register: Vec<Key>,
+    /// The bill values the machine can dispense. Any number of bills of each value is available.
+    denominations: Vec<u64>,
+    /// The bills handed out by the most recent withdrawal, as pairs of a denomination and how
+    /// many bills of it were dispensed, largest denomination first. Empty if it dispensed nothing.
+    last_dispense: Vec<(u64, u64)>,
+    /// The account ledger, mapping the pin hash of each card to its account balance.
+    accounts: HashMap<u64, u64>,
+    /// The pin hash of the card that has been authenticated in the current session, if any.
+    current_account: Option<u64>,
+    /// The balance shown by the most recent balance inquiry. `None` if the account
+    /// wasn't found in the ledger.
+    last_shown_balance: Option<u64>,
+    /// An audit log of every action the machine has processed, including the ones it ignored.
+    /// `None` if logging is turned off.
+    history: Option<Vec<Action>>,
+    /// The most that can be taken out in a single withdrawal, no matter how much cash is inside.
+    per_withdrawal_limit: u64,
+    /// How many ticks a partially entered pin may sit idle before the machine gives up on it.
+    pin_timeout: u64,
+    /// The ticks that have passed since the last key press.
+    idle_ticks: u64,
+    /// The outcome of the most recent withdrawal.
+    last_result: WithdrawalResult,
+}
+
+/// An empty machine waiting for a card. It dispenses bills of value 1, so any amount up to the
+/// cash inside can be withdrawn. Pin entry never times out.
+impl Default for Atm {
+    fn default() -> Self {
+        Self {
+            cash_inside: 0,
+            expected_pin_hash: Authentication::Waiting,
+            keystroke_register: Vec::new(),
+            denominations: vec![1],
+            last_dispense: Vec::new(),
+            accounts: HashMap::new(),
+            current_account: None,
+            last_shown_balance: None,
+            history: None,
+            per_withdrawal_limit: u64::MAX,
+            pin_timeout: u64::MAX,
+            idle_ticks: 0,
+            last_result: WithdrawalResult::Dispensed(0),
+        }
+    }
+}
+
+impl Atm {
+    /// Create an ATM holding the given amount of cash. It starts out waiting for a card
+    /// with an empty keystroke register.
+    pub fn new(cash: u64) -> Self {
+        Self {
+            cash_inside: cash,
+            ..Default::default()
+        }
+    }
+
+    /// Make a partially entered pin time out after the given number of ticks without a key
+    /// press. By default pin entry never times out.
+    pub fn with_pin_timeout(self, pin_timeout: u64) -> Self {
+        Self {
+            pin_timeout,
+            ..self
+        }
+    }
+
+    /// The machine's current authentication phase.
+    pub fn phase(&self) -> &Authentication {
+        &self.expected_pin_hash
+    }
+
+    /// How much money is in the machine.
+    pub fn cash_inside(&self) -> u64 {
+        self.cash_inside
+    }
+
+    /// The outcome of the most recent withdrawal.
+    pub fn last_result(&self) -> WithdrawalResult {
+        self.last_result
+    }
+}
+
+/// Break the amount into bills using greedy change-making, largest denomination first.
+/// The bills are returned as pairs of a denomination and how many bills of it to hand out.
+/// Returns `None` if the amount can't be expressed with the given denominations this way.
+fn make_change(amount: u64, denominations: &[u64]) -> Option<Vec<(u64, u64)>> {
+    let mut sorted_denominations: Vec<u64> = denominations.iter().copied().filter(|d| *d > 0).collect() ;
+    sorted_denominations.sort_unstable_by(|a, b| b.cmp(a)) ;
+    sorted_denominations.dedup() ;
+
+    // Count the bills of each denomination rather than listing them one by one, so that huge
+    // amounts don't take forever or run out of memory.
+    let mut remaining = amount ;
+    let mut bills = Vec::new() ;
+    for denomination in sorted_denominations {
+        let count = remaining / denomination ;
+        if count > 0 {
+            bills.push((denomination, count)) ;
+            remaining -= count * denomination ;
+        }
+    }
+
+    if remaining == 0 {
+        Some(bills)
+    } else {
+        None
+    }
 }
 
 impl StateMachine for Atm {
@@ -57,13 +194,26 @@ impl StateMachine for Atm {
     type Transition = Action;
 
     fn next_state(starting_state: &Self::State, transition: &Self::Transition) -> Self::State {
+        starting_state.try_next_state(transition)
+    }
+}
+
+impl Atm {
+    /// The core of the state machine. Any action is accepted in any state, and actions that
+    /// make no sense in the current state are simply ignored, so this never panics no matter
+    /// what sequence of actions it is fed.
+    pub fn try_next_state(&self, transition: &Action) -> Atm {
+        let starting_state = self ;
         let mut new_state = starting_state.clone();
         
         match transition {
             Action::SwipeCard(pin_hash) => {
                 match starting_state.expected_pin_hash {
                     Authentication::Waiting => {
-                        new_state.expected_pin_hash = Authentication::Authenticating(*pin_hash) ; 
+                        // Cards that aren't registered in the ledger are refused.
+                        if starting_state.accounts.is_empty() || starting_state.accounts.contains_key(pin_hash) {
+                            new_state.expected_pin_hash = Authentication::Authenticating(*pin_hash) ; 
+                        }
                     }
                     // Ignore "SwipeCard" action if not in Waiting state.
                     _ => {} 
@@ -74,11 +224,13 @@ impl StateMachine for Atm {
                     // Ignore key presses if waiting for card swipe.
                     Authentication::Waiting => {},
                     Authentication::Authenticating(expected_pin_hash) => {
+                        new_state.idle_ticks = 0 ;
                         if *key == Key::Enter {
                             // Check if entered pin's hash is equal to the expected pin hash.
                             let entered_pin_hash = crate::hash(&new_state.keystroke_register) ;
                             if entered_pin_hash == expected_pin_hash {
                                 new_state.expected_pin_hash = Authentication::Authenticated ;
+                                new_state.current_account = Some(expected_pin_hash) ;
                             }
                             else {
                                 new_state.expected_pin_hash = Authentication::Waiting ;
@@ -91,6 +243,8 @@ impl StateMachine for Atm {
                     }
                     Authentication::Authenticated => {
                         if *key == Key::Enter {
+                            // Saturate rather than overflow if someone keys in a huge amount. Such an
+                            // amount can't be dispensed anyway.
                             let amount_to_withdraw = new_state.keystroke_register.iter()
                             .filter_map(|k| match k {
                                 Key::One => Some(1),
@@ -98,13 +252,29 @@ impl StateMachine for Atm {
                                 Key::Three => Some(3),
                                 Key::Four => Some(4),
                                 _ => None,
-                            }).fold(0, |acc, digit| acc * 10 + digit as u64) ;
+                            }).fold(0u64, |acc, digit| acc.saturating_mul(10).saturating_add(digit)) ;
 
-                            if amount_to_withdraw <= new_state.cash_inside {
-                                new_state.cash_inside -= amount_to_withdraw ;
-                            }
+                            // Only dispense if the amount is within the withdrawal limit, the machine
+                            // has enough cash and it can be paid out in the bills the machine holds.
+                            // Either way, the card is returned.
+                            new_state.last_dispense.clear() ;
+                            new_state.last_result = if amount_to_withdraw > new_state.per_withdrawal_limit {
+                                WithdrawalResult::OverLimit
+                            } else if amount_to_withdraw > new_state.cash_inside {
+                                WithdrawalResult::InsufficientCash
+                            } else {
+                                match make_change(amount_to_withdraw, &new_state.denominations) {
+                                    Some(bills) => {
+                                        new_state.cash_inside -= amount_to_withdraw ;
+                                        new_state.last_dispense = bills ;
+                                        WithdrawalResult::Dispensed(amount_to_withdraw)
+                                    },
+                                    None => WithdrawalResult::InsufficientCash,
+                                }
+                            } ;
 
                             new_state.expected_pin_hash = Authentication::Waiting ;
+                            new_state.current_account = None ;
                             new_state.keystroke_register.clear() ;
                         }
                         else {
@@ -113,6 +283,75 @@ impl StateMachine for Atm {
                     }
                 }
             },
+            Action::BalanceInquiry => {
+                // Only an authenticated user may look up their balance.
+                if starting_state.expected_pin_hash == Authentication::Authenticated {
+                    new_state.last_shown_balance = starting_state.current_account
+                        .and_then(|account| starting_state.accounts.get(&account).copied()) ;
+                    new_state.expected_pin_hash = Authentication::Waiting ;
+                    new_state.current_account = None ;
+                    new_state.keystroke_register.clear() ;
+                }
+            },
+            Action::ChangePin(new_pin_hash) => {
+                if starting_state.expected_pin_hash == Authentication::Authenticated {
+                    // Move the balance over to the new pin hash. Changing to the same hash leaves
+                    // the ledger as it is, and we refuse to overwrite some other account.
+                    if let Some(old_pin_hash) = starting_state.current_account {
+                        if *new_pin_hash != old_pin_hash && !new_state.accounts.contains_key(new_pin_hash) {
+                            if let Some(balance) = new_state.accounts.remove(&old_pin_hash) {
+                                new_state.accounts.insert(*new_pin_hash, balance) ;
+                            }
+                        }
+                    }
+                    new_state.expected_pin_hash = Authentication::Waiting ;
+                    new_state.current_account = None ;
+                    new_state.keystroke_register.clear() ;
+                }
+            },
+            Action::WithdrawAll => {
+                if starting_state.expected_pin_hash == Authentication::Authenticated {
+                    // Pay out whichever runs out first, the machine's cash or the account's balance,
+                    // but never more than the withdrawal limit.
+                    new_state.last_dispense.clear() ;
+                    if let Some(account) = starting_state.current_account {
+                        let balance = starting_state.accounts.get(&account).copied().unwrap_or(0) ;
+                        let amount_to_withdraw = balance
+                            .min(starting_state.cash_inside)
+                            .min(starting_state.per_withdrawal_limit) ;
+                        new_state.last_result = match make_change(amount_to_withdraw, &new_state.denominations) {
+                            Some(bills) => {
+                                new_state.cash_inside -= amount_to_withdraw ;
+                                new_state.last_dispense = bills ;
+                                if let Some(balance) = new_state.accounts.get_mut(&account) {
+                                    *balance -= amount_to_withdraw ;
+                                }
+                                WithdrawalResult::Dispensed(amount_to_withdraw)
+                            },
+                            None => WithdrawalResult::InsufficientCash,
+                        } ;
+                    }
+                    new_state.expected_pin_hash = Authentication::Waiting ;
+                    new_state.current_account = None ;
+                    new_state.keystroke_register.clear() ;
+                }
+            },
+            Action::Tick => {
+                // Only a pin that was started but not finished can time out.
+                let entering_pin = matches!(starting_state.expected_pin_hash, Authentication::Authenticating(_))
+                    && !starting_state.keystroke_register.is_empty() ;
+                if entering_pin {
+                    new_state.idle_ticks = starting_state.idle_ticks.saturating_add(1) ;
+                    if new_state.idle_ticks >= starting_state.pin_timeout {
+                        new_state.expected_pin_hash = Authentication::Waiting ;
+                        new_state.keystroke_register.clear() ;
+                        new_state.idle_ticks = 0 ;
+                    }
+                }
+            },
+        }
+        if let Some(history) = new_state.history.as_mut() {
+            history.push(transition.clone()) ;
         }
         new_state
     }
@@ -125,12 +364,14 @@ fn sm_3_simple_swipe_card() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Waiting,
         keystroke_register: Vec::new(),
+        ..Default::default()
     } ;
     let end = Atm::next_state(&start, &Action::SwipeCard(1234)) ;
     let expected =Atm {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticating(1234),
-        keystroke_register: Vec::new(), 
+        keystroke_register: Vec::new(),
+        ..Default::default()
     } ;
 
     assert_eq!(end, expected) ;
@@ -142,12 +383,14 @@ fn sm_3_swipe_card_again_part_way_through() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticating(1234),
         keystroke_register: Vec::new(),
+        ..Default::default()
     } ;
     let end = Atm::next_state(&start, &Action::SwipeCard(1234)) ;
     let expected = Atm {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticating(1234),
-        keystroke_register: Vec::new(), 
+        keystroke_register: Vec::new(),
+        ..Default::default()
     } ; 
 
     assert_eq!(end, expected) ;
@@ -156,12 +399,14 @@ fn sm_3_swipe_card_again_part_way_through() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticating(1234),
         keystroke_register: vec![Key::One, Key::Three],
+        ..Default::default()
     } ;
     let end = Atm::next_state(&start, &Action::SwipeCard(1234)) ;
     let expected = Atm {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticating(1234),
         keystroke_register: vec![Key::One, Key::Three],
+        ..Default::default()
     } ;
 
     assert_eq!(end, expected) ;
@@ -173,12 +418,14 @@ fn sm_3_press_key_before_card_swipe() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Waiting,
         keystroke_register: Vec::new(),
+        ..Default::default()
     } ;
     let end = Atm::next_state(&start, &Action::PressKey(Key::One)) ;
     let expected = Atm {
         cash_inside: 10,
         expected_pin_hash: Authentication::Waiting,
         keystroke_register: Vec::new(),
+        ..Default::default()
     } ;
 
     assert_eq!(end, expected) ;
@@ -190,12 +437,14 @@ fn sm_3_enter_single_digit_of_pin() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticating(1234),
         keystroke_register: Vec::new(),
+        ..Default::default()
     } ;
     let end = Atm::next_state(&start, &Action::PressKey(Key::One)) ;
     let expected = Atm {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticating(1234),
         keystroke_register: vec![Key::One],
+        ..Default::default()
     } ;
 
     assert_eq!(end, expected) ;
@@ -204,12 +453,14 @@ fn sm_3_enter_single_digit_of_pin() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticating(1234),
         keystroke_register: vec![Key::One],
+        ..Default::default()
     } ;
     let end1 = Atm::next_state(&start, &Action::PressKey(Key::Two)) ;
     let expected1 = Atm {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticating(1234),
         keystroke_register: vec![Key::One, Key::Two],
+        ..Default::default()
     } ;
 
     assert_eq!(end1, expected1) ;
@@ -225,12 +476,14 @@ fn sm_3_enter_wrong_pin() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticating(pin_hash),
         keystroke_register: vec![Key::Three, Key::Three, Key::Three, Key::Three],
+        ..Default::default()
     } ;
     let end = Atm::next_state(&start, &Action::PressKey(Key::Enter)) ;
     let expected = Atm {
         cash_inside: 10,
         expected_pin_hash: Authentication::Waiting,
         keystroke_register: Vec::new(),
+        ..Default::default()
     } ;
 
     assert_eq!(end, expected) ;
@@ -246,12 +499,15 @@ fn sm_3_enter_correct_pin() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticating(pin_hash),
         keystroke_register: vec![Key::One, Key::Two, Key::Three, Key::Four],
+        ..Default::default()
     } ;
     let end = Atm::next_state(&start, &Action::PressKey(Key::Enter)) ;
     let expected = Atm {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticated,
         keystroke_register: Vec::new(),
+        current_account: Some(pin_hash),
+        ..Default::default()
     } ;
 
     assert_eq!(end, expected) ;
@@ -263,12 +519,14 @@ fn sm_3_enter_single_digit_of_withdraw_amount() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticated,
         keystroke_register: Vec::new(),
+        ..Default::default()
     } ;
     let end = Atm::next_state(&start, &Action::PressKey(Key::One)) ;
     let expected = Atm {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticated,
         keystroke_register: vec![Key::One],
+        ..Default::default()
     } ;
 
     assert_eq!(end, expected) ;
@@ -277,12 +535,14 @@ fn sm_3_enter_single_digit_of_withdraw_amount() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticated,
         keystroke_register: vec![Key::One],
+        ..Default::default()
     } ;
     let end1 = Atm::next_state(&start, &Action::PressKey(Key::Four)) ;
     let expected1 = Atm {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticated,
         keystroke_register: vec![Key::One, Key::Four],
+        ..Default::default()
     } ;
 
     assert_eq!(end1, expected1) ;
@@ -294,12 +554,15 @@ fn sm_3_try_to_withdraw_too_much() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticated,
         keystroke_register: vec![Key::One, Key::Four],
+        ..Default::default()
     } ;
     let end = Atm::next_state(&start, &Action::PressKey(Key::Enter)) ;
     let expected = Atm {
         cash_inside: 10,
         expected_pin_hash: Authentication::Waiting,
         keystroke_register: Vec::new(),
+        last_result: WithdrawalResult::InsufficientCash,
+        ..Default::default()
     } ;
 
     assert_eq!(end, expected) ;
@@ -311,12 +574,16 @@ fn sm_3_withdraw_acceptable_amount() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticated,
         keystroke_register: vec![Key::One],
+        ..Default::default()
     };
     let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
     let expected = Atm {
         cash_inside: 9,
         expected_pin_hash: Authentication::Waiting,
         keystroke_register: Vec::new(),
+        last_dispense: vec![(1, 1)],
+        last_result: WithdrawalResult::Dispensed(1),
+        ..Default::default()
     };
 
     assert_eq!(end, expected);
@@ -328,6 +595,7 @@ fn sm_3_end_to_end_atm_withdraw() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Waiting,
         keystroke_register: Vec::new(),
+        ..Default::default()
     } ;
     
     // Create hash of pin.
@@ -339,6 +607,7 @@ fn sm_3_end_to_end_atm_withdraw() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticating(pin_hash),
         keystroke_register: Vec::new(),
+        ..Default::default()
     } ;
 
     assert_eq!(end1, expected1) ;
@@ -347,12 +616,15 @@ fn sm_3_end_to_end_atm_withdraw() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticating(pin_hash),
         keystroke_register: vec![Key::One, Key::Two, Key::Three, Key::Four],
+        ..Default::default()
     } ;
     let end2 = Atm::next_state(&start2, &Action::PressKey(Key::Enter)) ;
     let expected2 = Atm {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticated,
         keystroke_register: Vec::new(),
+        current_account: Some(pin_hash),
+        ..Default::default()
     } ;
 
     assert_eq!(end2, expected2) ;
@@ -362,13 +634,519 @@ fn sm_3_end_to_end_atm_withdraw() {
         cash_inside: 10,
         expected_pin_hash: Authentication::Authenticated,
         keystroke_register: vec![Key::Four],
+        ..Default::default()
     } ;
     let end3 = Atm::next_state(&start3, &Action::PressKey(Key::Enter)) ;
     let expected3 = Atm {
         cash_inside: 6,
         expected_pin_hash: Authentication::Waiting,
         keystroke_register: Vec::new(),
+        last_dispense: vec![(1, 4)],
+        last_result: WithdrawalResult::Dispensed(4),
+        ..Default::default()
     } ;
 
     assert_eq!(end3, expected3) ;
-}
\ No newline at end of file
+}
+
+#[test]
+fn sm_3_withdraw_expressible_in_denominations() {
+    let start = Atm {
+        cash_inside: 100,
+        expected_pin_hash: Authentication::Authenticated,
+        keystroke_register: vec![Key::One, Key::Two],
+        denominations: vec![2, 10],
+        ..Default::default()
+    } ;
+    let end = Atm::next_state(&start, &Action::PressKey(Key::Enter)) ;
+    let expected = Atm {
+        cash_inside: 88,
+        expected_pin_hash: Authentication::Waiting,
+        keystroke_register: Vec::new(),
+        denominations: vec![2, 10],
+        last_dispense: vec![(10, 1), (2, 1)],
+        last_result: WithdrawalResult::Dispensed(12),
+        ..Default::default()
+    } ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_3_withdraw_huge_amount_counts_bills() {
+    // Listing every bill separately would take billions of entries.
+    let start = Atm {
+        cash_inside: u64::MAX,
+        expected_pin_hash: Authentication::Authenticated,
+        keystroke_register: vec![Key::Four; 10],
+        denominations: vec![1, 100],
+        ..Default::default()
+    } ;
+    let end = Atm::next_state(&start, &Action::PressKey(Key::Enter)) ;
+    let expected = Atm {
+        cash_inside: u64::MAX - 4_444_444_444,
+        expected_pin_hash: Authentication::Waiting,
+        keystroke_register: Vec::new(),
+        denominations: vec![1, 100],
+        last_dispense: vec![(100, 44_444_444), (1, 44)],
+        last_result: WithdrawalResult::Dispensed(4_444_444_444),
+        ..Default::default()
+    } ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_3_withdraw_inexpressible_in_denominations() {
+    let start = Atm {
+        cash_inside: 100,
+        expected_pin_hash: Authentication::Authenticated,
+        keystroke_register: vec![Key::One, Key::Three],
+        denominations: vec![2, 10],
+        ..Default::default()
+    } ;
+    let end = Atm::next_state(&start, &Action::PressKey(Key::Enter)) ;
+    let expected = Atm {
+        cash_inside: 100,
+        expected_pin_hash: Authentication::Waiting,
+        keystroke_register: Vec::new(),
+        denominations: vec![2, 10],
+        last_dispense: Vec::new(),
+        last_result: WithdrawalResult::InsufficientCash,
+        ..Default::default()
+    } ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_3_balance_inquiry_while_authenticated() {
+    let pin = vec![Key::One, Key::Two, Key::Three, Key::Four] ;
+    let pin_hash = crate::hash(&pin) ;
+
+    let start = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Authentication::Authenticated,
+        accounts: HashMap::from([(pin_hash, 250)]),
+        current_account: Some(pin_hash),
+        ..Default::default()
+    } ;
+    let end = Atm::next_state(&start, &Action::BalanceInquiry) ;
+    let expected = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Authentication::Waiting,
+        accounts: HashMap::from([(pin_hash, 250)]),
+        last_shown_balance: Some(250),
+        ..Default::default()
+    } ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_3_balance_inquiry_before_authentication() {
+    let start = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Authentication::Authenticating(1234),
+        keystroke_register: vec![Key::One],
+        accounts: HashMap::from([(1234, 250)]),
+        ..Default::default()
+    } ;
+    let end = Atm::next_state(&start, &Action::BalanceInquiry) ;
+
+    assert_eq!(end, start) ;
+}
+
+#[test]
+fn sm_3_change_pin_then_authenticate_with_new_pin() {
+    let old_pin_hash = crate::hash(&vec![Key::One, Key::Two, Key::Three, Key::Four]) ;
+    let new_pin = vec![Key::Four, Key::Three, Key::Two, Key::One] ;
+    let new_pin_hash = crate::hash(&new_pin) ;
+
+    let start = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Authentication::Authenticated,
+        accounts: HashMap::from([(old_pin_hash, 250)]),
+        current_account: Some(old_pin_hash),
+        ..Default::default()
+    } ;
+    let changed = Atm::next_state(&start, &Action::ChangePin(new_pin_hash)) ;
+    let expected = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Authentication::Waiting,
+        accounts: HashMap::from([(new_pin_hash, 250)]),
+        ..Default::default()
+    } ;
+    assert_eq!(changed, expected) ;
+
+    // The old card is no longer accepted.
+    let refused = Atm::next_state(&changed, &Action::SwipeCard(old_pin_hash)) ;
+    assert_eq!(refused, changed) ;
+
+    // The new pin authenticates.
+    let mut state = Atm::next_state(&changed, &Action::SwipeCard(new_pin_hash)) ;
+    for key in new_pin {
+        state = Atm::next_state(&state, &Action::PressKey(key)) ;
+    }
+    state = Atm::next_state(&state, &Action::PressKey(Key::Enter)) ;
+    assert_eq!(state.expected_pin_hash, Authentication::Authenticated) ;
+    assert_eq!(state.current_account, Some(new_pin_hash)) ;
+}
+
+#[test]
+fn sm_3_change_pin_to_same_hash() {
+    let pin_hash = crate::hash(&vec![Key::One, Key::Two, Key::Three, Key::Four]) ;
+
+    let start = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Authentication::Authenticated,
+        accounts: HashMap::from([(pin_hash, 250)]),
+        current_account: Some(pin_hash),
+        ..Default::default()
+    } ;
+    let end = Atm::next_state(&start, &Action::ChangePin(pin_hash)) ;
+    let expected = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Authentication::Waiting,
+        accounts: HashMap::from([(pin_hash, 250)]),
+        ..Default::default()
+    } ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_3_new_atm_is_waiting_with_cash() {
+    let atm = Atm::new(10) ;
+
+    assert_eq!(atm.cash_inside, 10) ;
+    assert_eq!(atm.expected_pin_hash, Authentication::Waiting) ;
+    assert!(atm.keystroke_register.is_empty()) ;
+}
+
+#[test]
+fn sm_3_withdraw_all_limited_by_account() {
+    let start = Atm {
+        cash_inside: 100,
+        expected_pin_hash: Authentication::Authenticated,
+        accounts: HashMap::from([(1234, 30)]),
+        current_account: Some(1234),
+        ..Default::default()
+    } ;
+    let end = Atm::next_state(&start, &Action::WithdrawAll) ;
+    let expected = Atm {
+        cash_inside: 70,
+        expected_pin_hash: Authentication::Waiting,
+        accounts: HashMap::from([(1234, 0)]),
+        last_dispense: vec![(1, 30)],
+        last_result: WithdrawalResult::Dispensed(30),
+        ..Default::default()
+    } ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_3_withdraw_all_limited_by_machine() {
+    let start = Atm {
+        cash_inside: 20,
+        expected_pin_hash: Authentication::Authenticated,
+        denominations: vec![5, 10],
+        accounts: HashMap::from([(1234, 250)]),
+        current_account: Some(1234),
+        ..Default::default()
+    } ;
+    let end = Atm::next_state(&start, &Action::WithdrawAll) ;
+    let expected = Atm {
+        cash_inside: 0,
+        expected_pin_hash: Authentication::Waiting,
+        denominations: vec![5, 10],
+        accounts: HashMap::from([(1234, 230)]),
+        last_dispense: vec![(10, 2)],
+        last_result: WithdrawalResult::Dispensed(20),
+        ..Default::default()
+    } ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_3_withdraw_all_before_authentication() {
+    let start = Atm {
+        cash_inside: 20,
+        expected_pin_hash: Authentication::Authenticating(1234),
+        accounts: HashMap::from([(1234, 250)]),
+        ..Default::default()
+    } ;
+    let end = Atm::next_state(&start, &Action::WithdrawAll) ;
+
+    assert_eq!(end, start) ;
+}
+
+#[test]
+fn sm_3_history_logs_full_withdraw() {
+    let pin = vec![Key::One, Key::Two] ;
+    let pin_hash = crate::hash(&pin) ;
+    let actions = vec![
+        Action::PressKey(Key::One),
+        Action::SwipeCard(pin_hash),
+        Action::PressKey(Key::One),
+        Action::PressKey(Key::Two),
+        Action::PressKey(Key::Enter),
+        Action::PressKey(Key::Three),
+        Action::PressKey(Key::Enter),
+    ] ;
+
+    let mut state = Atm {
+        cash_inside: 10,
+        history: Some(Vec::new()),
+        ..Default::default()
+    } ;
+    for action in actions.iter() {
+        state = Atm::next_state(&state, action) ;
+    }
+
+    // The key press before the card was swiped did nothing, but it is still logged.
+    assert_eq!(state.cash_inside, 7) ;
+    assert_eq!(state.history, Some(actions)) ;
+}
+
+#[test]
+fn sm_3_withdraw_above_limit_is_rejected() {
+    // The keypad has no nine, so ask for 12 instead.
+    let start = Atm {
+        cash_inside: 100,
+        expected_pin_hash: Authentication::Authenticated,
+        keystroke_register: vec![Key::One, Key::Two],
+        per_withdrawal_limit: 5,
+        ..Default::default()
+    } ;
+    let end = Atm::next_state(&start, &Action::PressKey(Key::Enter)) ;
+    let expected = Atm {
+        cash_inside: 100,
+        expected_pin_hash: Authentication::Waiting,
+        per_withdrawal_limit: 5,
+        last_result: WithdrawalResult::OverLimit,
+        ..Default::default()
+    } ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_3_withdraw_all_capped_by_limit() {
+    let start = Atm {
+        cash_inside: 100,
+        expected_pin_hash: Authentication::Authenticated,
+        accounts: HashMap::from([(1234, 50)]),
+        current_account: Some(1234),
+        per_withdrawal_limit: 5,
+        ..Default::default()
+    } ;
+    let end = Atm::next_state(&start, &Action::WithdrawAll) ;
+
+    assert_eq!(end.cash_inside, 95) ;
+    assert_eq!(end.accounts, HashMap::from([(1234, 45)])) ;
+    assert_eq!(end.last_dispense, vec![(1, 5)]) ;
+}
+
+/// Drive an ATM through a long random sequence of card swipes and key presses, checking the
+/// invariants after every step. Half of the swipes use a card whose pin is empty, so pressing
+/// enter right away authenticates and the random keys that follow turn into withdrawals.
+#[cfg(test)]
+fn fuzz_atm(seed: u64, steps: usize) {
+    use rand::{rngs::StdRng, Rng, SeedableRng} ;
+
+    let mut rng = StdRng::seed_from_u64(seed) ;
+    let empty_pin_hash = crate::hash(&Vec::<Key>::new()) ;
+    let mut state = Atm::new(1_000) ;
+
+    for _ in 0..steps {
+        let action = match rng.gen_range(0..7) {
+            0 if rng.gen_bool(0.5) => Action::SwipeCard(empty_pin_hash),
+            0 => Action::SwipeCard(rng.gen()),
+            1 => Action::PressKey(Key::One),
+            2 => Action::PressKey(Key::Two),
+            3 => Action::PressKey(Key::Three),
+            4 => Action::PressKey(Key::Four),
+            _ => Action::PressKey(Key::Enter),
+        } ;
+        let next = state.try_next_state(&action) ;
+
+        assert!(next.cash_inside <= state.cash_inside, "cash increased after {:?}", action) ;
+        if action == Action::PressKey(Key::Enter) {
+            assert!(next.keystroke_register.is_empty(), "register not cleared after enter") ;
+        }
+        state = next ;
+    }
+}
+
+#[test]
+fn sm_3_fuzz_random_actions() {
+    for seed in 0..50 {
+        fuzz_atm(seed, 1_000) ;
+    }
+}
+
+#[test]
+fn sm_3_amount_too_long_for_u64_saturates() {
+    // 21 digits don't fit in a u64. Parsing this used to panic with an overflow. The amount
+    // now saturates at `u64::MAX`, which is just more than the machine holds, so nothing is
+    // dispensed. A wrapped amount would have been small enough to pay out.
+    let start = Atm {
+        cash_inside: u64::MAX - 1,
+        expected_pin_hash: Authentication::Authenticated,
+        keystroke_register: vec![Key::Four; 21],
+        ..Default::default()
+    } ;
+    let end = Atm::next_state(&start, &Action::PressKey(Key::Enter)) ;
+    let expected = Atm {
+        cash_inside: u64::MAX - 1,
+        expected_pin_hash: Authentication::Waiting,
+        last_result: WithdrawalResult::InsufficientCash,
+        ..Default::default()
+    } ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_3_huge_amount_is_not_dispensed() {
+    let start = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Authentication::Authenticated,
+        keystroke_register: vec![Key::Four; 25],
+        ..Default::default()
+    } ;
+    let end = Atm::next_state(&start, &Action::PressKey(Key::Enter)) ;
+    let expected = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Authentication::Waiting,
+        last_result: WithdrawalResult::InsufficientCash,
+        ..Default::default()
+    } ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_3_accessors_after_swipe() {
+    let start = Atm::new(10) ;
+    assert_eq!(start.phase(), &Authentication::Waiting) ;
+
+    let end = Atm::next_state(&start, &Action::SwipeCard(1234)) ;
+    assert_eq!(end.phase(), &Authentication::Authenticating(1234)) ;
+    assert_eq!(end.cash_inside(), 10) ;
+}
+
+#[test]
+fn sm_3_partial_pin_times_out() {
+    let mut state = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Authentication::Authenticating(1234),
+        pin_timeout: 3,
+        ..Default::default()
+    } ;
+    state = Atm::next_state(&state, &Action::PressKey(Key::One)) ;
+    state = Atm::next_state(&state, &Action::Tick) ;
+    state = Atm::next_state(&state, &Action::Tick) ;
+    assert_eq!(state.phase(), &Authentication::Authenticating(1234)) ;
+    assert_eq!(state.keystroke_register, vec![Key::One]) ;
+
+    state = Atm::next_state(&state, &Action::Tick) ;
+    let expected = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Authentication::Waiting,
+        pin_timeout: 3,
+        ..Default::default()
+    } ;
+    assert_eq!(state, expected) ;
+}
+
+#[test]
+fn sm_3_pin_entered_before_timeout() {
+    let pin = vec![Key::One, Key::Two] ;
+    let mut state = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Authentication::Authenticating(crate::hash(&pin)),
+        pin_timeout: 2,
+        ..Default::default()
+    } ;
+    // Every key press restarts the timeout.
+    for key in pin {
+        state = Atm::next_state(&state, &Action::PressKey(key)) ;
+        state = Atm::next_state(&state, &Action::Tick) ;
+    }
+    state = Atm::next_state(&state, &Action::PressKey(Key::Enter)) ;
+
+    assert_eq!(state.phase(), &Authentication::Authenticated) ;
+}
+
+#[test]
+fn sm_3_tick_without_partial_pin_is_ignored() {
+    let start = Atm {
+        cash_inside: 10,
+        expected_pin_hash: Authentication::Authenticating(1234),
+        pin_timeout: 1,
+        ..Default::default()
+    } ;
+    let end = Atm::next_state(&start, &Action::Tick) ;
+
+    assert_eq!(end, start) ;
+}
+
+#[test]
+fn sm_3_withdrawal_results() {
+    let authenticated = |keys: Vec<Key>| Atm {
+        cash_inside: 20,
+        expected_pin_hash: Authentication::Authenticated,
+        keystroke_register: keys,
+        per_withdrawal_limit: 30,
+        ..Default::default()
+    } ;
+
+    let dispensed = Atm::next_state(&authenticated(vec![Key::One, Key::Two]), &Action::PressKey(Key::Enter)) ;
+    assert_eq!(dispensed.last_result(), WithdrawalResult::Dispensed(12)) ;
+
+    let over_cash = Atm::next_state(&authenticated(vec![Key::Two, Key::Four]), &Action::PressKey(Key::Enter)) ;
+    assert_eq!(over_cash.last_result(), WithdrawalResult::InsufficientCash) ;
+    assert_eq!(over_cash.cash_inside(), 20) ;
+
+    let over_limit = Atm::next_state(&authenticated(vec![Key::Three, Key::One]), &Action::PressKey(Key::Enter)) ;
+    assert_eq!(over_limit.last_result(), WithdrawalResult::OverLimit) ;
+    assert_eq!(over_limit.cash_inside(), 20) ;
+}
+
+#[test]
+fn sm_3_withdraw_all_results() {
+    let authenticated = |denominations: Vec<u64>| Atm {
+        cash_inside: 100,
+        expected_pin_hash: Authentication::Authenticated,
+        denominations,
+        accounts: HashMap::from([(1234, 7)]),
+        current_account: Some(1234),
+        last_result: WithdrawalResult::OverLimit,
+        ..Default::default()
+    } ;
+
+    let dispensed = Atm::next_state(&authenticated(vec![1, 5]), &Action::WithdrawAll) ;
+    assert_eq!(dispensed.last_result(), WithdrawalResult::Dispensed(7)) ;
+
+    // The whole balance of 7 can't be paid in bills of 5.
+    let unpayable = Atm::next_state(&authenticated(vec![5]), &Action::WithdrawAll) ;
+    assert_eq!(unpayable.last_result(), WithdrawalResult::InsufficientCash) ;
+    assert_eq!(unpayable.cash_inside(), 100) ;
+}
+
+#[test]
+fn sm_3_configured_pin_timeout() {
+    let pin = vec![Key::One, Key::Two] ;
+    let mut state = Atm::new(10).with_pin_timeout(2) ;
+    state = Atm::next_state(&state, &Action::SwipeCard(crate::hash(&pin))) ;
+    state = Atm::next_state(&state, &Action::PressKey(Key::One)) ;
+    state = Atm::next_state(&state, &Action::Tick) ;
+    assert_eq!(state.phase(), &Authentication::Authenticating(crate::hash(&pin))) ;
+
+    state = Atm::next_state(&state, &Action::Tick) ;
+    assert_eq!(state, Atm::new(10).with_pin_timeout(2)) ;
+}
@@ -8,62 +8,171 @@
 //! In this module we design a state machine that tracks the currency balances of several users.
 //! Each user is associated with an account balance and users are able to send money to other users.
 
-use super::{StateMachine, User} ;
+use super::{Account, StateMachine, User} ;
 use std::collections::HashMap ;
+use std::marker::PhantomData ;
 
 /// This state machine models a multi-user currency system. It tracks the balance of each user
 /// and allows user to send funds to one another.
-pub struct AccountedCurrency ;
+///
+/// Accounts are identified by the play users unless another account type, such as an
+/// `AccountId`, is given.
+pub struct AccountedCurrency<A = User>(PhantomData<A>) ;
+
+/// Identifies one of the currencies tracked by the system. Each asset is fully independent,
+/// so balances in one asset can never be used to pay in another.
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub struct AssetId(pub u32) ;
 
 /// The main balances mapping.
 /// 
-/// Each entry maps a user id to their corresponding balance.
-/// There exists an existential deposit of atleast 1. That is 
-/// to say that an account gets removed from the map entirely
+/// Each entry maps an account and an asset id to the account's balance in that asset.
+/// There exists an existential deposit of atleast 1 per asset. That is 
+/// to say that a user's entry for an asset gets removed from the map entirely
 /// when its balance falls back to 0.
-type Balances = HashMap<User, u64> ;
+type Balances<A = User> = HashMap<(A, AssetId), u64> ;
+
+/// The most of any single asset that may ever be in existence. A mint that would push the total
+/// issuance of its asset past this cap is rejected as a whole, rather than minting part of it.
+/// Burning makes room for minting again.
+pub const MAX_SUPPLY: u64 = 21_000_000 ;
+
+/// Create an empty balances mapping, where nobody holds any asset yet.
+pub fn new_balances<A: Account>() -> Balances<A> {
+    HashMap::new()
+}
+
+/// The total amount of the given asset in existence, summed over all users.
+pub fn total_issuance<A: Account>(balances: &Balances<A>, asset: AssetId) -> u64 {
+    balances.iter()
+        .filter(|((_, balance_asset), _)| *balance_asset == asset)
+        .fold(0u64, |total, (_, balance)| total.saturating_add(*balance))
+}
+
+/// Every account holding the given asset, sorted from the largest balance to the smallest.
+/// Accounts with equal balances are ordered by user, so the result doesn't depend on the
+/// iteration order of the map.
+pub fn leaderboard<A: Account + Ord>(balances: &Balances<A>, asset: AssetId) -> Vec<(A, u64)> {
+    let mut accounts: Vec<(A, u64)> = balances.iter()
+        .filter(|((_, balance_asset), _)| *balance_asset == asset)
+        .map(|((user, _), balance)| (*user, *balance))
+        .collect() ;
+    accounts.sort_by(|(user_1, balance_1), (user_2, balance_2)| {
+        balance_2.cmp(balance_1).then(user_1.cmp(user_2))
+    }) ;
+    accounts
+}
+
+/// The signed change in each account's balance of the given asset between two states, which
+/// makes the effect of a transition easy to check. An account that appeared counts as going up
+/// from zero, and one that disappeared as going down to zero. Accounts whose balance didn't
+/// change are left out, and the rest are sorted by account.
+pub fn diff<A: Account + Ord>(before: &Balances<A>, after: &Balances<A>, asset: AssetId) -> Vec<(A, i128)> {
+    let balance = |balances: &Balances<A>, user: A| balances.get(&(user, asset)).copied().unwrap_or(0) as i128 ;
+
+    let mut users: Vec<A> = before.keys().chain(after.keys())
+        .filter(|(_, balance_asset)| *balance_asset == asset)
+        .map(|(user, _)| *user)
+        .collect() ;
+    users.sort() ;
+    users.dedup() ;
+
+    users.into_iter()
+        .map(|user| (user, balance(after, user) - balance(before, user)))
+        .filter(|(_, change)| *change != 0)
+        .collect()
+}
+
+/// The amount `numerator / denominator` of the balance comes to, rounded down. Returns `None`
+/// for a zero denominator, or if the multiplication overflows.
+fn fraction_of(balance: u64, numerator: u64, denominator: u64) -> Option<u64> {
+    balance.checked_mul(numerator)?.checked_div(denominator)
+}
 
 /// The state transitions that users can make in an accounted currency system.
-pub enum AccountingTransaction {
+/// Every transition acts on a single asset.
+#[derive(Debug, Clone)]
+pub enum AccountingTransaction<A = User> {
     /// Create some new money for the given minter in the given amount.
-    Mint { minter: User, amount: u64},
+    /// Nothing is minted if it would take the asset over `MAX_SUPPLY`.
+    Mint { minter: A, amount: u64, asset: AssetId },
     /// Destroy some money from the given account in the given amount.
     /// If burn amount exceeds the account balance, burn the entire amount 
     /// and remove the account from the storage.
-    Burn { burner: User, amount: u64},
-    /// Send some amount from one account to another.
+    Burn { burner: A, amount: u64, asset: AssetId },
+    /// Take some money away from the target account as a penalty, and destroy it.
+    /// Unlike a burn, which the account holder chooses to make, a slash is imposed by the
+    /// protocol without the target's consent. Otherwise it behaves like a burn: slashing more
+    /// than the balance takes all of it, and the account is removed once it drops below the
+    /// existential deposit.
+    Slash { target: A, amount: u64, asset: AssetId },
+    /// Destroy the given fraction of the burner's balance, rounded down. Otherwise it behaves
+    /// like a burn of that amount: a fraction above one takes the whole balance, and the
+    /// account is removed once it drops below the existential deposit.
+    /// Nothing is burned if the denominator is zero or the fraction can't be computed.
+    BurnFraction {
+        burner: A,
+        numerator: u64,
+        denominator: u64,
+        asset: AssetId,
+    },
+    /// Send some amount from one account to another. Money only moves within the given asset.
     Transfer {
-        sender: User,
-        receiver: User,
+        sender: A,
+        receiver: A,
         amount: u64,
-    }
+        asset: AssetId,
+    },
+    /// Send money from one account to several receivers at once, within the given asset.
+    ///
+    /// The batch is atomic: either every transfer in it goes through, or none does. It is
+    /// rejected as a whole if the sender can't cover the sum of the amounts. Unless
+    /// `create_missing` is set, it is also rejected as a whole if any receiver doesn't have an
+    /// account in the asset yet. With `create_missing` set, such receivers get a new account.
+    /// Entries that send nothing, or that send to the sender, are skipped.
+    BatchTransfer {
+        sender: A,
+        transfers: Vec<(A, u64)>,
+        asset: AssetId,
+        create_missing: bool,
+    },
+    /// Do nothing. This always returns the starting state unchanged, which makes it handy as
+    /// padding in a sequence of transactions.
+    NoOp,
 }
 
-impl StateMachine for AccountedCurrency {
-    type State = Balances;
-    type Transition = AccountingTransaction;
+impl<A: Account> StateMachine for AccountedCurrency<A> {
+    type State = Balances<A>;
+    type Transition = AccountingTransaction<A>;
 
-    fn next_state(starting_state: &Balances, transition: &AccountingTransaction) -> Balances {
+    fn next_state(starting_state: &Balances<A>, transition: &AccountingTransaction<A>) -> Balances<A> {
         use AccountingTransaction::* ;
 
         let mut new_state = starting_state.clone() ;
 
         match transition {
-            Mint { minter, amount } => {
+            Mint { minter, amount, asset } => {
                 // If the mint amount is equal to 0, we don't mint anything.
                 if *amount == 0 {
                     return new_state;
                 }
-                let balances = new_state.entry(*minter).or_insert(0) ;
+                // If the mint would exceed the maximum supply of the asset, we don't mint anything.
+                match total_issuance(&new_state, *asset).checked_add(*amount) {
+                    Some(issuance) if issuance <= MAX_SUPPLY => {},
+                    _ => return new_state,
+                }
+                let balances = new_state.entry((*minter, *asset)).or_insert(0) ;
                 *balances += amount ;
             }
-            Burn { burner, amount} => {
+            Burn { burner, amount, asset } | Slash { target: burner, amount, asset } => {
+                let burner = (*burner, *asset) ;
+
                 // If burner is not present in the Balances map, we don't burn anything.
-                if !new_state.contains_key(burner) {
+                if !new_state.contains_key(&burner) {
                     return new_state;
                 }
                 // Get old amount of burner.
-                let old_amount = *new_state.get(burner).unwrap() ;
+                let old_amount = *new_state.get(&burner).unwrap() ;
 
                 // Calculate new amount for burner.
                 let new_amount = old_amount.saturating_sub(*amount);
@@ -71,20 +180,38 @@ impl StateMachine for AccountedCurrency {
                 // If the new amount results into less than or equal to zero, we remove the user, else,
                 // we update the Balances map with new amount.
                 if new_amount <= 0 {
-                    new_state.remove(burner) ;
+                    new_state.remove(&burner) ;
                 }
                 else {
-                    new_state.insert(*burner, new_amount) ;
+                    new_state.insert(burner, new_amount) ;
                 }
             }
-            Transfer { sender, receiver, amount} => {
+            BurnFraction { burner, numerator, denominator, asset } => {
+                let balance = new_state.get(&(*burner, *asset)).copied().unwrap_or(0) ;
+                // If the fraction can't be computed, we don't burn anything.
+                let amount = match fraction_of(balance, *numerator, *denominator) {
+                    Some(amount) => amount,
+                    None => return new_state,
+                } ;
+                return Self::next_state(&new_state, &Burn { burner: *burner, amount, asset: *asset }) ;
+            }
+            Transfer { sender, receiver, amount, asset } => {
+                // If the sender and receiver are same user, or nothing is sent, we don't transfer
+                // anything. A zero transfer would otherwise open an empty account for the receiver.
+                if sender == receiver || *amount == 0 {
+                    return new_state;
+                }
+
+                let sender = (*sender, *asset) ;
+                let receiver = (*receiver, *asset) ;
+
                 // If the sender or receiver is unregistered, we don't transfer anything.
-                if !new_state.contains_key(sender) {
+                if !new_state.contains_key(&sender) {
                     return new_state;
                 }
 
                 // Get balance amount of sender.
-                let old_amount_of_sender = *new_state.get(sender).unwrap() ;
+                let old_amount_of_sender = *new_state.get(&sender).unwrap() ;
 
                 // If the amount to be sent is greater than the balance amount of sender, 
                 // we don't transfer anyting.
@@ -92,43 +219,124 @@ impl StateMachine for AccountedCurrency {
                     return new_state;
                 } 
 
-                // If the sender and receiver are same user, we don't transfer anything.
-                if new_state.get(sender) == new_state.get(receiver) {
-                    return new_state;
-                }
-
                 // If the receiver does not exist in the Balances map in the starting state, 
                 // we insert the receiver with balance amount, else, if the receiver is pre-existing,
                 // we get the old balance of receiver and update it.
-                if !new_state.contains_key(receiver) {
-                    new_state.insert(*receiver, *amount) ;
+                if !new_state.contains_key(&receiver) {
+                    new_state.insert(receiver, *amount) ;
                     let new_amount_of_sender = old_amount_of_sender.saturating_sub(*amount) ;
                     if new_amount_of_sender <= 0 {
-                        new_state.remove(sender) ;
+                        new_state.remove(&sender) ;
                     }
                     else {
-                        new_state.insert(*sender, new_amount_of_sender) ;
+                        new_state.insert(sender, new_amount_of_sender) ;
                     }
                 } else {
                     // Get balance of receiver.
-                    let old_amount_of_receiver = *new_state.get(receiver).unwrap() ;
+                    let old_amount_of_receiver = *new_state.get(&receiver).unwrap() ;
 
                     // Calculate the updated balance of receiver and sender.
                     let new_amount_of_sender = old_amount_of_sender.saturating_sub(*amount) ;
                     let new_amount_of_receiver = old_amount_of_receiver.saturating_add(*amount) ;
                     if new_amount_of_sender <= 0 {
-                        new_state.remove(sender) ;
+                        new_state.remove(&sender) ;
                     } else {
-                        new_state.insert(*sender, new_amount_of_sender) ;
+                        new_state.insert(sender, new_amount_of_sender) ;
                     }
-                    new_state.insert(*receiver, new_amount_of_receiver) ;
+                    new_state.insert(receiver, new_amount_of_receiver) ;
                 }
             }
+            BatchTransfer { sender, transfers, asset, create_missing } => {
+                let sender_key = (*sender, *asset) ;
+                let moves = |(receiver, amount): &&(A, u64)| receiver != sender && *amount > 0 ;
+
+                // Check the whole batch before moving anything, so that it is all or nothing.
+                let Some(old_amount_of_sender) = new_state.get(&sender_key).copied() else {
+                    return new_state;
+                } ;
+                let mut total: u64 = 0 ;
+                for (receiver, amount) in transfers.iter().filter(moves) {
+                    if !create_missing && !new_state.contains_key(&(*receiver, *asset)) {
+                        return new_state;
+                    }
+                    total = match total.checked_add(*amount) {
+                        Some(total) => total,
+                        None => return new_state,
+                    } ;
+                }
+                if old_amount_of_sender < total {
+                    return new_state;
+                }
+
+                for (receiver, amount) in transfers.iter().filter(moves) {
+                    let balance = new_state.entry((*receiver, *asset)).or_insert(0) ;
+                    *balance = balance.saturating_add(*amount) ;
+                }
+                let new_amount_of_sender = old_amount_of_sender - total ;
+                if new_amount_of_sender == 0 {
+                    new_state.remove(&sender_key) ;
+                } else {
+                    new_state.insert(sender_key, new_amount_of_sender) ;
+                }
+            }
+            NoOp => {}
         }
         new_state
     }
 }
 
+impl<A: Account> AccountedCurrency<A> {
+    /// Apply the transition like `next_state`, and check that it changed the total issuance of
+    /// its asset by exactly as much as it should have. Mints add their amount unless that would
+    /// exceed the maximum supply, burns, fractional burns and slashes remove as much as the account held up to
+    /// their amount, and transfers leave it unchanged.
+    ///
+    /// This is a development aid for catching bugs in `next_state`. In debug builds a violation
+    /// panics with a description of the transition, while in release builds the check is
+    /// skipped entirely, issuance scans included, and this behaves exactly like `next_state`.
+    pub fn checked_next_state(starting_state: &Balances<A>, transition: &AccountingTransaction<A>) -> Balances<A> {
+        use AccountingTransaction::* ;
+
+        let new_state = Self::next_state(starting_state, transition) ;
+
+        // Only pay for the issuance scans in debug builds.
+        if cfg!(debug_assertions) {
+            let (asset, expected_issuance) = match transition {
+                Mint { amount, asset, .. } => {
+                    let issuance = total_issuance(starting_state, *asset) ;
+                    match issuance.checked_add(*amount) {
+                        Some(minted) if minted <= MAX_SUPPLY => (*asset, minted),
+                        _ => (*asset, issuance),
+                    }
+                },
+                Burn { burner: user, amount, asset } | Slash { target: user, amount, asset } => {
+                    let balance = starting_state.get(&(*user, *asset)).copied().unwrap_or(0) ;
+                    (*asset, total_issuance(starting_state, *asset) - balance.min(*amount))
+                },
+                BurnFraction { burner, numerator, denominator, asset } => {
+                    let balance = starting_state.get(&(*burner, *asset)).copied().unwrap_or(0) ;
+                    let burned = fraction_of(balance, *numerator, *denominator).unwrap_or(0) ;
+                    (*asset, total_issuance(starting_state, *asset) - balance.min(burned))
+                },
+                Transfer { asset, .. } | BatchTransfer { asset, .. } => {
+                    (*asset, total_issuance(starting_state, *asset))
+                },
+                // Nothing changes, so there is no issuance to check.
+                NoOp => return new_state,
+            } ;
+            assert_eq!(
+                total_issuance(&new_state, asset),
+                expected_issuance,
+                "{:?} changed the total issuance of {:?} by the wrong amount",
+                transition,
+                asset
+            ) ;
+        }
+
+        new_state
+    }
+}
+
 #[cfg(test)]
 #[test]
 fn sm_4_mint_creates_account() {
@@ -137,40 +345,43 @@ fn sm_4_mint_creates_account() {
         &start, 
         &AccountingTransaction::Mint { 
             minter: User::Alice, 
-            amount: 100 
+            amount: 100,
+            asset: AssetId(0),
         }
     ) ;
-    let expected = HashMap::from([(User::Alice, 100)]) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
 
     assert_eq!(end, expected) ; 
 }
 
 #[test]
 fn sm_4_mint_creates_second_account() {
-    let start = HashMap::from([(User::Alice, 100)]) ;
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Mint {
             minter: User::Bob,
             amount: 50,
+            asset: AssetId(0),
         },
     ) ;
-    let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
 
     assert_eq!(end, expected) ;
 }
 
 #[test]
 fn sm_4_mint_increases_balance() {
-    let start = HashMap::from([(User::Alice, 100)]) ;
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
     let end = AccountedCurrency::next_state(
         &start, 
         &AccountingTransaction::Mint { 
             minter: User::Alice, 
-            amount: 50, 
+            amount: 50,
+            asset: AssetId(0),
         }
     ) ;
-    let expected = HashMap::from([(User::Alice, 150)]) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 150)]) ;
 
     assert_eq!(end, expected) ;
 }
@@ -183,6 +394,7 @@ fn sm_4_empty_mint() {
         &AccountingTransaction::Mint {
             minter: User::Alice,
             amount: 0,
+            asset: AssetId(0),
         },
     ) ;
     let expected = HashMap::new() ;
@@ -192,215 +404,720 @@ fn sm_4_empty_mint() {
 
 #[test]
 fn sm_4_simple_burn() {
-    let start = HashMap::from([(User::Alice, 100)]) ;
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
             burner: User::Alice,
             amount: 50,
+            asset: AssetId(0),
         },
     ) ;
-    let expected = HashMap::from([(User::Alice, 50)]) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 50)]) ;
 
     assert_eq!(end, expected) ;
 }
 
 #[test]
 fn sm_4_burn_no_existential_deposit_left() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]) ;
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
             burner: User::Bob,
             amount: 50,
+            asset: AssetId(0),
         },
     ) ;
-    let expected = HashMap::from([(User::Alice, 100)]) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
 
     assert_eq!(end, expected) ;
 }
 
 #[test]
 fn sm_4_non_registered_burner() {
-    let start = HashMap::from([(User::Alice, 100)]) ;
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
             burner: User::Bob,
             amount: 50,
+            asset: AssetId(0),
         },
     ) ;
-    let expected = HashMap::from([(User::Alice, 100)]) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
 
     assert_eq!(end, expected) ;
 }
 
 #[test]
 fn sm_4_burn_more_than_balance() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]) ;
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
             burner: User::Bob,
             amount: 100,
+            asset: AssetId(0),
         },
     ) ;
-    let expected = HashMap::from([(User::Alice, 100)]) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
 
     assert_eq!(end, expected) ;
 }
 
 #[test]
 fn sm_4_empty_burn() {
-    let start = HashMap::from([(User::Alice, 100)]) ;
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
             burner: User::Alice,
             amount: 0,
+            asset: AssetId(0),
         },
     ) ;
-    let expected = HashMap::from([(User::Alice, 100)]) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
 
     assert_eq!(end, expected) ;
 }
 
 #[test]
 fn sm_4_burner_does_not_exist() {
-    let start = HashMap::from([(User::Alice, 100)]) ;
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
             burner: User::Bob,
             amount: 50,
+            asset: AssetId(0),
         },
     ) ;
-    let expected = HashMap::from([(User::Alice, 100)]) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
 
     assert_eq!(end, expected) ;
 }
 
 #[test]
 fn sm_4_simple_transfer() {
-    let start1 = HashMap::from([(User::Alice, 100), (User::Bob, 50)]) ;
+    let start1 = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
     let end1 = AccountedCurrency::next_state(
         &start1, 
         &AccountingTransaction::Transfer { 
             sender: User::Alice, 
             receiver: User::Bob, 
-            amount: 20, 
+            amount: 20,
+            asset: AssetId(0),
         }
     ) ;
-    let expected1 = HashMap::from([(User::Alice, 80), (User::Bob, 70)]) ;
+    let expected1 = HashMap::from([((User::Alice, AssetId(0)), 80), ((User::Bob, AssetId(0)), 70)]) ;
 
     assert_eq!(end1, expected1) ;
 
-    let start2 = HashMap::from([(User::Alice, 80), (User::Bob, 70)]) ;
+    let start2 = HashMap::from([((User::Alice, AssetId(0)), 80), ((User::Bob, AssetId(0)), 70)]) ;
     let end2 = AccountedCurrency::next_state(
         &start2, 
         &AccountingTransaction::Transfer { 
             sender: User::Bob, 
             receiver: User::Alice, 
-            amount: 50 
+            amount: 50,
+            asset: AssetId(0),
         }
     ) ;
-    let expected2 = HashMap::from([(User::Alice, 130), (User::Bob, 20)]) ;
+    let expected2 = HashMap::from([((User::Alice, AssetId(0)), 130), ((User::Bob, AssetId(0)), 20)]) ;
 
     assert_eq!(end2, expected2) ;
 }
 
 #[test]
 fn sm_4_send_to_same_user() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]) ;
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Transfer {
             sender: User::Bob,
             receiver: User::Bob,
             amount: 10,
+            asset: AssetId(0),
         },
     ) ;
-    let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
 
     assert_eq!(end, expected) ;
 }
 
 #[test]
 fn sm_4_insufficient_balance_transfer() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]) ;
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Transfer {
             sender: User::Bob,
             receiver: User::Alice,
             amount: 60,
+            asset: AssetId(0),
         },
     ) ;
-    let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
 
     assert_eq!(end, expected) ;
 }
 
 #[test]
 fn sm_4_sender_not_registered() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]) ;
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Transfer {
             sender: User::Charlie,
             receiver: User::Alice,
             amount: 50,
+            asset: AssetId(0),
         },
     ) ;
-    let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
 
     assert_eq!(end, expected) ;
 }
 
 #[test]
 fn sm_4_receiver_not_registered() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]) ;
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Transfer {
             sender: User::Alice,
             receiver: User::Charlie,
             amount: 50,
+            asset: AssetId(0),
         },
     ) ;
-    let expected = HashMap::from([(User::Alice, 50), (User::Bob, 50), (User::Charlie, 50)]) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 50), ((User::Bob, AssetId(0)), 50), ((User::Charlie, AssetId(0)), 50)]) ;
 
     assert_eq!(end, expected) ;
 }
 
 #[test]
 fn sm_4_sender_to_empty_balance() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]) ;
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Transfer {
             sender: User::Bob,
             receiver: User::Alice,
             amount: 50,
+            asset: AssetId(0),
         },
     ) ;
-    let expected = HashMap::from([(User::Alice, 150)]) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 150)]) ;
 
     assert_eq!(end, expected) ;
 }
 
 #[test]
 fn sm_4_transfer() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]) ;
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Transfer {
             sender: User::Bob,
             receiver: User::Charlie,
             amount: 50,
+            asset: AssetId(0),
+        },
+    ) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Charlie, AssetId(0)), 50)]) ;
+
+    assert_eq!(end, expected) ;
+}
+#[test]
+fn sm_4_transfer_one_asset_leaves_other_asset_untouched() {
+    let start = HashMap::from([
+        ((User::Alice, AssetId(0)), 100),
+        ((User::Alice, AssetId(1)), 40),
+        ((User::Bob, AssetId(1)), 10),
+    ]) ;
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Bob,
+            amount: 60,
+            asset: AssetId(0),
+        },
+    ) ;
+    let expected = HashMap::from([
+        ((User::Alice, AssetId(0)), 40),
+        ((User::Alice, AssetId(1)), 40),
+        ((User::Bob, AssetId(0)), 60),
+        ((User::Bob, AssetId(1)), 10),
+    ]) ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_4_cannot_pay_in_asset_without_balance() {
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Bob,
+            amount: 10,
+            asset: AssetId(1),
+        },
+    ) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_4_mint_into_new_balances() {
+    let start = new_balances() ;
+    assert!(start.is_empty()) ;
+
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::Mint {
+            minter: User::Bob,
+            amount: 20,
+            asset: AssetId(0),
         },
     ) ;
-    let expected = HashMap::from([(User::Alice, 100), (User::Charlie, 50)]) ;
+    let expected = HashMap::from([((User::Bob, AssetId(0)), 20)]) ;
 
     assert_eq!(end, expected) ;
-}
\ No newline at end of file
+}
+
+#[test]
+fn sm_4_transfer_between_users_with_equal_balances() {
+    let start = HashMap::from([((User::Alice, AssetId(0)), 50), ((User::Bob, AssetId(0)), 50)]) ;
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Bob,
+            amount: 10,
+            asset: AssetId(0),
+        },
+    ) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 40), ((User::Bob, AssetId(0)), 60)]) ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_4_total_issuance_per_asset() {
+    let balances = HashMap::from([
+        ((User::Alice, AssetId(0)), 100),
+        ((User::Bob, AssetId(0)), 50),
+        ((User::Bob, AssetId(1)), 7),
+    ]) ;
+
+    assert_eq!(total_issuance(&balances, AssetId(0)), 150) ;
+    assert_eq!(total_issuance(&balances, AssetId(1)), 7) ;
+    assert_eq!(total_issuance(&balances, AssetId(2)), 0) ;
+}
+
+#[test]
+fn sm_4_slash_part_of_balance() {
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::Slash {
+            target: User::Bob,
+            amount: 20,
+            asset: AssetId(0),
+        },
+    ) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 30)]) ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_4_slash_all_of_balance_removes_account() {
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::Slash {
+            target: User::Bob,
+            amount: 80,
+            asset: AssetId(0),
+        },
+    ) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_4_leaderboard_orders_by_balance() {
+    let balances = HashMap::from([
+        ((User::Alice, AssetId(0)), 50),
+        ((User::Bob, AssetId(0)), 100),
+        ((User::Charlie, AssetId(0)), 50),
+        ((User::Charlie, AssetId(1)), 500),
+    ]) ;
+
+    assert_eq!(
+        leaderboard(&balances, AssetId(0)),
+        vec![(User::Bob, 100), (User::Alice, 50), (User::Charlie, 50)]
+    ) ;
+}
+
+#[test]
+fn sm_4_mint_up_to_max_supply() {
+    let start = HashMap::from([((User::Alice, AssetId(0)), MAX_SUPPLY - 100)]) ;
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::Mint {
+            minter: User::Bob,
+            amount: 100,
+            asset: AssetId(0),
+        },
+    ) ;
+
+    assert_eq!(total_issuance(&end, AssetId(0)), MAX_SUPPLY) ;
+    assert_eq!(end.get(&(User::Bob, AssetId(0))), Some(&100)) ;
+}
+
+#[test]
+fn sm_4_mint_past_max_supply_is_rejected() {
+    let start = HashMap::from([((User::Alice, AssetId(0)), MAX_SUPPLY - 100)]) ;
+    let end = AccountedCurrency::checked_next_state(
+        &start,
+        &AccountingTransaction::Mint {
+            minter: User::Bob,
+            amount: 101,
+            asset: AssetId(0),
+        },
+    ) ;
+
+    assert_eq!(end, start) ;
+}
+
+#[test]
+fn sm_4_max_supply_is_per_asset() {
+    let start = HashMap::from([((User::Alice, AssetId(0)), MAX_SUPPLY)]) ;
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::Mint {
+            minter: User::Alice,
+            amount: 100,
+            asset: AssetId(1),
+        },
+    ) ;
+
+    assert_eq!(end.get(&(User::Alice, AssetId(1))), Some(&100)) ;
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn sm_4_checked_transfer_conserves_issuance() {
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
+    let end = AccountedCurrency::checked_next_state(
+        &start,
+        &AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Charlie,
+            amount: 30,
+            asset: AssetId(0),
+        },
+    ) ;
+    let expected = HashMap::from([
+        ((User::Alice, AssetId(0)), 70),
+        ((User::Bob, AssetId(0)), 50),
+        ((User::Charlie, AssetId(0)), 30),
+    ]) ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_4_ring_of_ten_accounts() {
+    use super::AccountId ;
+
+    let accounts: Vec<AccountId> = (0..10).map(AccountId).collect() ;
+    let mut state: Balances<AccountId> = accounts.iter().map(|account| ((*account, AssetId(0)), 10)).collect() ;
+
+    // Every account passes money to the next one, and the last one back to the first.
+    for (index, sender) in accounts.iter().enumerate() {
+        let receiver = accounts[(index + 1) % accounts.len()] ;
+        state = AccountedCurrency::next_state(
+            &state,
+            &AccountingTransaction::Transfer {
+                sender: *sender,
+                receiver,
+                amount: index as u64 + 1,
+                asset: AssetId(0),
+            },
+        ) ;
+    }
+
+    assert_eq!(total_issuance(&state, AssetId(0)), 100) ;
+    // The first account sent 1 and received 10 from the last one. Everybody else sent one
+    // more than they received.
+    assert_eq!(state[&(AccountId(0), AssetId(0))], 19) ;
+    for account in &accounts[1..] {
+        assert_eq!(state[&(*account, AssetId(0))], 9) ;
+    }
+}
+
+#[test]
+fn sm_4_play_users_as_account_ids() {
+    let start: Balances<super::AccountId> = HashMap::from([((User::Alice.into(), AssetId(0)), 100)]) ;
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::Transfer {
+            sender: User::Alice.into(),
+            receiver: User::Bob.into(),
+            amount: 40,
+            asset: AssetId(0),
+        },
+    ) ;
+
+    assert_eq!(leaderboard(&end, AssetId(0)), vec![(User::Alice.into(), 60), (User::Bob.into(), 40)]) ;
+}
+
+#[test]
+fn sm_4_batch_transfer_rejects_missing_receiver() {
+    let start = HashMap::from([
+        ((User::Alice, AssetId(0)), 100),
+        ((User::Bob, AssetId(0)), 10),
+    ]) ;
+    let end = AccountedCurrency::checked_next_state(
+        &start,
+        &AccountingTransaction::BatchTransfer {
+            sender: User::Alice,
+            transfers: vec![(User::Bob, 20), (User::Charlie, 30)],
+            asset: AssetId(0),
+            create_missing: false,
+        },
+    ) ;
+
+    // Bob is registered, but Charlie isn't, so not even Bob's part goes through.
+    assert_eq!(end, start) ;
+}
+
+#[test]
+fn sm_4_batch_transfer_creates_missing_receivers() {
+    let start = HashMap::from([
+        ((User::Alice, AssetId(0)), 100),
+        ((User::Bob, AssetId(0)), 10),
+    ]) ;
+    let end = AccountedCurrency::checked_next_state(
+        &start,
+        &AccountingTransaction::BatchTransfer {
+            sender: User::Alice,
+            transfers: vec![(User::Bob, 20), (User::Charlie, 30), (User::Charlie, 50)],
+            asset: AssetId(0),
+            create_missing: true,
+        },
+    ) ;
+    let expected = HashMap::from([
+        ((User::Bob, AssetId(0)), 30),
+        ((User::Charlie, AssetId(0)), 80),
+    ]) ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_4_batch_transfer_beyond_balance_is_rejected() {
+    let start = HashMap::from([
+        ((User::Alice, AssetId(0)), 100),
+        ((User::Bob, AssetId(0)), 10),
+    ]) ;
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::BatchTransfer {
+            sender: User::Alice,
+            transfers: vec![(User::Bob, 60), (User::Bob, 60)],
+            asset: AssetId(0),
+            create_missing: true,
+        },
+    ) ;
+
+    assert_eq!(end, start) ;
+}
+
+#[test]
+fn sm_4_diff_after_transfer() {
+    let before = HashMap::from([
+        ((User::Alice, AssetId(0)), 100),
+        ((User::Bob, AssetId(0)), 50),
+        ((User::Charlie, AssetId(0)), 20),
+        ((User::Charlie, AssetId(1)), 20),
+    ]) ;
+    let after = AccountedCurrency::next_state(
+        &before,
+        &AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Bob,
+            amount: 30,
+            asset: AssetId(0),
+        },
+    ) ;
+
+    assert_eq!(diff(&before, &after, AssetId(0)), vec![(User::Alice, -30), (User::Bob, 30)]) ;
+    assert_eq!(diff(&before, &after, AssetId(1)), vec![]) ;
+}
+
+#[test]
+fn sm_4_diff_includes_appearing_and_disappearing_accounts() {
+    let before = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
+    let after = AccountedCurrency::next_state(
+        &before,
+        &AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Charlie,
+            amount: 100,
+            asset: AssetId(0),
+        },
+    ) ;
+
+    assert_eq!(diff(&before, &after, AssetId(0)), vec![(User::Alice, -100), (User::Charlie, 100)]) ;
+}
+
+#[test]
+fn sm_4_noop_is_identity() {
+    let start = HashMap::from([
+        ((User::Alice, AssetId(0)), 100),
+        ((User::Bob, AssetId(1)), 50),
+    ]) ;
+    assert_eq!(AccountedCurrency::checked_next_state(&start, &AccountingTransaction::NoOp), start) ;
+
+    // Padding a sequence with no-ops doesn't change where it ends up.
+    let transfer = AccountingTransaction::Transfer {
+        sender: User::Alice,
+        receiver: User::Charlie,
+        amount: 30,
+        asset: AssetId(0),
+    } ;
+    let padded = [AccountingTransaction::NoOp, transfer.clone(), AccountingTransaction::NoOp] ;
+    let end = padded.iter().fold(start.clone(), |state, tx| AccountedCurrency::next_state(&state, tx)) ;
+    assert_eq!(end, AccountedCurrency::next_state(&start, &transfer)) ;
+}
+
+#[test]
+fn sm_4_burn_half_of_balance() {
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100), ((User::Bob, AssetId(0)), 50)]) ;
+    let end = AccountedCurrency::checked_next_state(
+        &start,
+        &AccountingTransaction::BurnFraction {
+            burner: User::Alice,
+            numerator: 1,
+            denominator: 2,
+            asset: AssetId(0),
+        },
+    ) ;
+    let expected = HashMap::from([((User::Alice, AssetId(0)), 50), ((User::Bob, AssetId(0)), 50)]) ;
+
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_4_burn_third_of_balance() {
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
+    let burn_third = AccountingTransaction::BurnFraction {
+        burner: User::Alice,
+        numerator: 1,
+        denominator: 3,
+        asset: AssetId(0),
+    } ;
+
+    // A third of 100 rounds down to 33.
+    let end = AccountedCurrency::checked_next_state(&start, &burn_third) ;
+    assert_eq!(end, HashMap::from([((User::Alice, AssetId(0)), 67)])) ;
+
+    // Burning the whole balance leaves nothing, so the account is removed.
+    let all = AccountingTransaction::BurnFraction {
+        burner: User::Alice,
+        numerator: 3,
+        denominator: 3,
+        asset: AssetId(0),
+    } ;
+    assert_eq!(AccountedCurrency::checked_next_state(&end, &all), HashMap::new()) ;
+}
+
+#[test]
+fn sm_4_burn_fraction_rejects_zero_denominator_and_overflow() {
+    let start = HashMap::from([((User::Alice, AssetId(0)), 100)]) ;
+    let burn = |numerator, denominator| AccountingTransaction::BurnFraction {
+        burner: User::Alice,
+        numerator,
+        denominator,
+        asset: AssetId(0),
+    } ;
+
+    assert_eq!(AccountedCurrency::checked_next_state(&start, &burn(1, 0)), start) ;
+    assert_eq!(AccountedCurrency::checked_next_state(&start, &burn(u64::MAX, u64::MAX)), start) ;
+}
+
+#[cfg(test)]
+use proptest::prelude::* ;
+
+/// Generates arbitrary transfers among the play users in one of two assets. Every part is drawn
+/// from a small range, so failing cases shrink down to something readable.
+#[cfg(test)]
+fn arb_transfer() -> impl Strategy<Value = AccountingTransaction> {
+    let user = prop_oneof![Just(User::Alice), Just(User::Bob), Just(User::Charlie)] ;
+    (user.clone(), user, 0..150u64, 0..2u32).prop_map(|(sender, receiver, amount, asset)| {
+        AccountingTransaction::Transfer { sender, receiver, amount, asset: AssetId(asset) }
+    })
+}
+
+/// Every play user starts out funded in both assets.
+#[cfg(test)]
+fn funded_balances() -> Balances {
+    HashMap::from([
+        ((User::Alice, AssetId(0)), 100),
+        ((User::Bob, AssetId(0)), 50),
+        ((User::Charlie, AssetId(0)), 50),
+        ((User::Alice, AssetId(1)), 10),
+        ((User::Bob, AssetId(1)), 20),
+        ((User::Charlie, AssetId(1)), 30),
+    ])
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn sm_4_prop_transfers_conserve_issuance(transfers in prop::collection::vec(arb_transfer(), 0..40)) {
+        let start = funded_balances() ;
+        let mut state = start.clone() ;
+        for transfer in transfers.iter() {
+            state = AccountedCurrency::checked_next_state(&state, transfer) ;
+
+            for asset in [AssetId(0), AssetId(1)] {
+                prop_assert_eq!(total_issuance(&state, asset), total_issuance(&start, asset)) ;
+            }
+            // No balance wraps around, and nobody is left with an empty account.
+            for (&(_, asset), &balance) in state.iter() {
+                prop_assert!(balance > 0) ;
+                prop_assert!(balance <= total_issuance(&start, asset)) ;
+            }
+        }
+    }
+
+    #[test]
+    fn sm_4_prop_affordable_transfer_moves_exact_amount(transfer in arb_transfer()) {
+        let start = funded_balances() ;
+        let end = AccountedCurrency::next_state(&start, &transfer) ;
+
+        if let AccountingTransaction::Transfer { sender, receiver, amount, asset } = transfer {
+            let before = |user| start.get(&(user, asset)).copied().unwrap_or(0) ;
+            let after = |user| end.get(&(user, asset)).copied().unwrap_or(0) ;
+
+            if sender != receiver && amount <= before(sender) {
+                prop_assert_eq!(after(sender), before(sender) - amount) ;
+                prop_assert_eq!(after(receiver), before(receiver) + amount) ;
+            } else {
+                prop_assert_eq!(end, start) ;
+            }
+        }
+    }
+}
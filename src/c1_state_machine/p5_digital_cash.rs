@@ -3,42 +3,93 @@
 //! bills. Each bill has an amount and an owner, and can be spent in its entirety. When 
 //! a state transition spends bills, new bills are created in lesser or equal amounts.
 
-use super::{StateMachine, User} ;
+use super::{Account, StateMachine, User} ;
 use std::collections::{HashMap,HashSet} ;
+use std::fmt ;
+use std::marker::PhantomData ;
 
 /// This state machine models a multi-user currency system. It tracks a set of bills 
 /// in circulation, and updates the set when money is transferred.
-pub struct DigitalCashSystem;
+///
+/// Bills are owned by the play users unless another account type, such as an `AccountId`,
+/// is given.
+pub struct DigitalCashSystem<A = User>(PhantomData<A>) ;
+
+/// The smallest amount a newly created bill may be worth. Anything below it is dust, and would
+/// only flood the set of circulating bills.
+///
+/// This also applies to change. When the bills a user spends are worth a little more than they
+/// want to send, and the remainder is less than `MIN_BILL`, they can't give themselves change.
+/// The remainder either has to be left out, in which case it is destroyed, or be sent along.
+pub const MIN_BILL: u64 = 5 ;
 
 /// A single bill in the digital cash system. Each bill has an owner who is allowed to spent it
 /// and an amount that it is worth. It also has a serial number to ensure that each bill
-/// is unique.
+/// is unique. A bill may also carry an expiry height, after which it is no longer valid.
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
-pub struct Bill {
-    owner: User,
+pub struct Bill<A = User> {
+    owner: A,
     amount: u64,
     serial: u64,
+    /// The height at which the bill expires, if any. Bills without an expiry never expire.
+    expiry: Option<u64>,
+}
+
+impl<A> Bill<A> {
+    /// Create a bill that never expires.
+    pub fn new(owner: A, amount: u64, serial: u64) -> Self {
+        Self { owner, amount, serial, expiry: None }
+    }
+
+    // Check whether the bill has expired at the given height.
+    fn is_expired_at(&self, height: u64) -> bool {
+        self.expiry.is_some_and(|expiry| height >= expiry)
+    }
 }
 
 /// The State of the digital cash system. Primarily, it is just a set of circulating bills,
 /// but also a counter for the next serial number.
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct State {
+#[derive(Debug, Clone)]
+pub struct State<A = User> {
     /// The set of currently circulating bills.
-    bills: HashSet<Bill>,
+    bills: HashSet<Bill<A>>,
     /// The next serial number to use when a bill is created.
     next_serial: u64,
+    /// The circulating bills keyed by their serial number. This is kept in sync with `bills`
+    /// so that a bill can be looked up without knowing its owner and amount.
+    serial_index: HashMap<u64, Bill<A>>,
+    /// The current block height, which is advanced by `Tick` and decides when bills expire.
+    current_height: u64,
+}
+
+/// The serial index is derived entirely from the bills, so two states are equal when their
+/// bills, next serial and height are.
+impl<A: Account> PartialEq for State<A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bills == other.bills
+            && self.next_serial == other.next_serial
+            && self.current_height == other.current_height
+    }
 }
 
-impl State {
+impl<A: Account> Eq for State<A> {}
+
+impl<A: Account> State<A> {
     // Create a new instance of our State.
     pub fn new() -> Self {
         Self {
             bills: HashSet::new(),
             next_serial: 0,
+            serial_index: HashMap::new(),
+            current_height: 0,
         }
     }
 
+    // Return the current block height.
+    pub fn current_height(&self) -> u64 {
+        self.current_height
+    }
+
     // Set serial for the created bill.
     pub fn set_serial(&mut self, serial: u64) {
         self.next_serial = serial ; 
@@ -54,156 +105,520 @@ impl State {
         self.next_serial += 1 
     }
 
-    // Add new bill to the Bill's set.
-    fn add_bill(&mut self, elem: Bill) {
+    // Return the circulating bill with the given serial, if any.
+    pub fn get_by_serial(&self, serial: u64) -> Option<&Bill<A>> {
+        self.serial_index.get(&serial)
+    }
+
+    // Check whether some circulating bill already carries the given serial.
+    fn serial_in_use(&self, serial: u64) -> bool {
+        self.serial_index.contains_key(&serial)
+    }
+
+    // Insert a bill into the Bill's set and the serial index.
+    fn insert_bill(&mut self, elem: Bill<A>) {
+        self.serial_index.insert(elem.serial, elem.clone()) ;
         self.bills.insert(elem) ;
+    }
+
+    // Remove a bill from the Bill's set and the serial index.
+    fn remove_bill(&mut self, elem: &Bill<A>) {
+        if self.serial_index.get(&elem.serial) == Some(elem) {
+            self.serial_index.remove(&elem.serial) ;
+        }
+        self.bills.remove(elem) ;
+    }
+
+    // Remove every bill from circulation.
+    fn clear_bills(&mut self) {
+        self.bills.clear() ;
+        self.serial_index.clear() ;
+    }
+
+    // Add new bill to the Bill's set.
+    fn add_bill(&mut self, elem: Bill<A>) {
+        self.insert_bill(elem) ;
         self.increment_serial() 
     }
-}
 
-impl FromIterator<Bill> for State {
-    fn from_iter<T: IntoIterator<Item = Bill>>(iter: T) -> Self {
+    /// Builds a state from the given bills, like `State::from_iter`, but returns
+    /// `CashError::DuplicateSerial` instead of panicking if two of the bills share a serial.
+    pub fn try_from_bills<T: IntoIterator<Item = Bill<A>>>(bills: T) -> Result<Self, CashError> {
         let mut state = State::new() ;
 
-        for i in iter {
-            state.add_bill(i)
+        for bill in bills {
+            if state.serial_in_use(bill.serial) {
+                return Err(CashError::DuplicateSerial) ;
+            }
+            state.insert_bill(bill) ;
+        }
+        if let Some(max_serial) = state.bills.iter().map(|bill| bill.serial).max() {
+            state.set_serial(max_serial.saturating_add(1)) ;
+        }
+        Ok(state)
+    }
+}
+
+impl<A: Account> FromIterator<Bill<A>> for State<A> {
+    /// Builds a state from the given bills. The next serial is set one past the largest
+    /// serial among them, so that freshly minted bills never collide with existing ones.
+    ///
+    /// # Panics
+    ///
+    /// Panics if two of the bills share a serial, since every serial has to identify exactly
+    /// one bill in circulation. Use `State::try_from_bills` when the bills aren't known to be
+    /// unique.
+    fn from_iter<T: IntoIterator<Item = Bill<A>>>(iter: T) -> Self {
+        match State::try_from_bills(iter) {
+            Ok(state) => state,
+            Err(_) => panic!("Two bills share a serial."),
         }
-        state
     }
 }
 
-impl<const N: usize> From<[Bill; N]> for State {
-    fn from(value: [Bill; N]) -> Self {
+impl<A: Account, const N: usize> From<[Bill<A>; N]> for State<A> {
+    fn from(value: [Bill<A>; N]) -> Self {
         State::from_iter(value)
     }
 }
 
+/// A short summary of the state, which is much easier to read in a failing test than the
+/// full set of bills.
+impl<A> fmt::Display for State<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.bills.iter().fold(0u64, |total, bill| total.saturating_add(bill.amount)) ;
+        write!(
+            f,
+            "State {{ bills: {}, total: {}, next_serial: {} }}",
+            self.bills.len(),
+            total,
+            self.next_serial
+        )
+    }
+}
+
 /// The state transitions that users can make in the digital cash system.
-pub enum CashTransaction {
-    /// Mint a single new bill owned by the minter.
-    Mint { minter: User, amount: u64},
+pub enum CashTransaction<A = User> {
+    /// Mint a single new bill owned by the minter. Just like transfers can't create zero-value
+    /// bills, minting zero does nothing.
+    Mint { minter: A, amount: u64},
+    /// Mint one new bill per listed denomination for the minter, all in one go. This is
+    /// convenient for handing out realistic change. Unlike a plain mint, a zero denomination
+    /// is rejected, and so is one below `MIN_BILL`.
+    MintDenominated { minter: A, denominations: Vec<u64> },
     /// Send some money from some users to other users. The money does not all need to 
     /// come from the same user, and it does not all need to go to the same user.
     /// The total amount received must be less than or equal to the amount spent.
     /// The discrepancy between the amount sent and received is destroyed. Therefore,
     /// no dedicated burn transaction is required.
     Transfer {
-        spends: Vec<Bill>,
-        receives: Vec<Bill>,
+        spends: Vec<Bill<A>>,
+        receives: Vec<Bill<A>>,
     },
+    /// Advance the clock by one block. Every bill whose expiry height has been reached is
+    /// removed from circulation.
+    Tick,
+    /// Break the bill with the given serial into smaller bills owned by the same user, without
+    /// needing a counterparty. The new amounts must add up to at most the original amount, and
+    /// any remainder is destroyed.
+    Split { serial: u64, amounts: Vec<u64> },
+    /// Give the bill with the given serial to a new owner. The bill is consumed and replaced by
+    /// one of the same amount and expiry, owned by `new_owner` and carrying a fresh serial.
+    SendBill { serial: u64, new_owner: A },
+    /// Do nothing. This always returns the starting state unchanged, which makes it handy as
+    /// padding in a sequence of transactions.
+    NoOp,
 }
 
-/// We model this system as a state machine with two possible transitions.
-impl StateMachine for DigitalCashSystem {
-    type State = State; 
-    type Transition = CashTransaction;
+/// The reasons a cash transaction can be rejected.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CashError {
+    /// A spent bill is not in circulation.
+    BillDoesNotExist,
+    /// The same bill is spent more than once within a single transaction.
+    DuplicateSpend,
+    /// A received bill uses the last possible serial, leaving no room for the next one.
+    SerialOverflow,
+    /// A received bill reuses the serial of another bill in the same transaction or of a bill
+    /// still in circulation, or two merged states both have a bill with the same serial.
+    DuplicateSerial,
+    /// The received bills are worth more than the spent ones.
+    SpendingLimitExceeded,
+    /// The transaction doesn't create any value.
+    ZeroOutput,
+    /// A created bill is worth less than `MIN_BILL`.
+    Dust,
+    /// A transaction in a batch spends a bill that an earlier transaction already spent.
+    DoubleSpend { tx_index: usize },
+    /// A bill in the transaction has already expired.
+    BillExpired,
+}
 
-    fn next_state(starting_state: &Self::State, transition: &Self::Transition) -> Self::State {
+impl fmt::Display for CashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CashError::BillDoesNotExist => write!(f, "Bill does not exist."),
+            CashError::DuplicateSpend => write!(f, "Invalid serial number."),
+            CashError::SerialOverflow => write!(f, "Invalid serial number with overflow."),
+            CashError::DuplicateSerial => write!(f, "Spend and receive bills cannot be same"),
+            CashError::SpendingLimitExceeded => write!(f, "Spending limit exceeded."),
+            CashError::ZeroOutput => write!(f, "Output of 0 value"),
+            CashError::Dust => write!(f, "Output below the minimum bill of {}.", MIN_BILL),
+            CashError::DoubleSpend { tx_index } => {
+                write!(f, "Transaction {} spends a bill that was already spent.", tx_index)
+            },
+            CashError::BillExpired => write!(f, "Bill has expired."),
+        }
+    }
+}
+
+impl<A: Account> DigitalCashSystem<A> {
+    /// Apply the transition like `next_state`, but report why an invalid transition is rejected
+    /// instead of silently falling back to the starting state.
+    pub fn try_next_state(starting_state: &State<A>, transition: &CashTransaction<A>) -> Result<State<A>, CashError> {
         use CashTransaction::* ;
 
         let mut new_state = starting_state.clone() ;
         match transition {
             Mint { minter, amount} => {
-                let new_bill = Bill {
-                    owner: *minter,
-                    amount: *amount,
-                    serial: new_state.next_serial(),
-                } ;
+                // Zero-value bills are forbidden, just like in transfers.
+                if *amount == 0 {
+                    return Ok(new_state) ;
+                }
+                // Minting dust is rejected, just like creating it in a transfer.
+                if *amount < MIN_BILL {
+                    return Err(CashError::Dust) ;
+                }
+                // The next serial may already be taken in a hand-constructed state. Advance past
+                // any serial in use, so that the new bill never replaces an existing one.
+                while new_state.serial_in_use(new_state.next_serial()) {
+                    new_state.increment_serial() ;
+                }
+                let new_bill = Bill::new(*minter, *amount, new_state.next_serial()) ;
                 new_state.add_bill(new_bill) ;
-                return new_state ;
+                Ok(new_state)
+            },
+            MintDenominated { minter, denominations } => {
+                // Check every denomination first, so that nothing is minted if any is invalid.
+                for denomination in denominations {
+                    if *denomination == 0 {
+                        return Err(CashError::ZeroOutput) ;
+                    }
+                    if *denomination < MIN_BILL {
+                        return Err(CashError::Dust) ;
+                    }
+                }
+                for denomination in denominations {
+                    while new_state.serial_in_use(new_state.next_serial()) {
+                        new_state.increment_serial() ;
+                    }
+                    let new_bill = Bill::new(*minter, *denomination, new_state.next_serial()) ;
+                    new_state.add_bill(new_bill) ;
+                }
+                Ok(new_state)
             },
             Transfer { spends, receives } => {
                 // If 'spends' is empty, no change in state.
                 if spends.is_empty() {
-                    return new_state ;
+                    return Ok(new_state) ;
                 }
 
                 // If 'receives' is empty, we return empty bill in current state.
                 if receives.is_empty() {
-                    new_state.bills = HashSet::default() ;
-                    return new_state ;
+                    new_state.clear_bills() ;
+                    return Ok(new_state) ;
                 }
 
-                // Closure to handle balance tranfer.
-                let transfer_process = |new_state: &mut State| -> Result<(), &'static str> {
-                    let spend_id = "spend" ;
-                    let receive_id = "receive" ;
-                    let mut visited_serial: HashMap<(&'static str, u64), bool> = HashMap::default() ;
-                    let mut total_spends: u64 = 0 ;
-                    let mut total_receives: u64 = 0 ;
-
-                    // Iterate over 'spends'
-                    for bill in spends {
-                        // If spend bill is not present in the current state, we return Err.
-                        if !new_state.bills.contains(bill) {
-                            return Err("Bill does not exist.");
-                        }
+                let spend_id = "spend" ;
+                let receive_id = "receive" ;
+                let mut visited_serial: HashMap<(&'static str, u64), bool> = HashMap::default() ;
+                let mut total_spends: u64 = 0 ;
+                let mut total_receives: u64 = 0 ;
 
-                        // If spending serial is found to be a duplicate in current state, we return Err.
-                        if visited_serial.contains_key(&(spend_id, bill.serial)) {
-                            return Err("Invalid serial number.");
-                        }
+                // Iterate over 'spends'
+                for bill in spends {
+                    // If spend bill is not present in the current state, we return Err.
+                    if new_state.get_by_serial(bill.serial) != Some(bill) {
+                        return Err(CashError::BillDoesNotExist);
+                    }
+
+                    // If spend bill has already expired, we return Err.
+                    if bill.is_expired_at(new_state.current_height()) {
+                        return Err(CashError::BillExpired);
+                    }
+
+                    // If spending serial is found to be a duplicate in current state, we return Err.
+                    if visited_serial.contains_key(&(spend_id, bill.serial)) {
+                        return Err(CashError::DuplicateSpend);
+                    }
+
+                    // Make the current spend bill as visited, so that we can check in receive later.
+                    visited_serial.insert((spend_id, bill.serial), true) ;
 
-                        // Make the current spend bill as visited, so that we can check in receive later.
-                        visited_serial.insert((spend_id, bill.serial), true) ;
+                    // Remove spend bill from HashSet of current state after it is being spent.
+                    new_state.remove_bill(bill) ;
 
-                        // Remove spend bill from HashSet of current state after it is being spent.
-                        new_state.bills.remove(bill) ;
+                    // Update 'total_spends'.
+                    total_spends = total_spends.saturating_add(bill.amount) ;
+                }
 
-                        // Update 'total_spends'.
-                        total_spends = total_spends.saturating_add(bill.amount) ;                          
+                // Iterate over 'receives'.
+                for bill in receives {
+                    // If the serial value is invalid, we return Err.
+                    if bill.serial == u64::MAX {
+                        return Err(CashError::SerialOverflow) ;
                     }
 
-                    // Iterate over 'receives'.
-                    for bill in receives {
-                        // If the serial value is invalid, we return Err.
-                        if bill.serial == u64::MAX {
-                            return Err("Invalid serial number with overflow.") ;
-                        }
+                    // A bill that would be expired on arrival is worthless, so we return Err.
+                    if bill.is_expired_at(new_state.current_height()) {
+                        return Err(CashError::BillExpired) ;
+                    }
 
-                        // If serial of spend or receive bill comes out to be same, identified by 'serial', we return Err.
-                        if visited_serial.contains_key(&(spend_id, bill.serial)) || 
-                            visited_serial.contains_key(&(receive_id, bill.serial)) {
-                                return Err("Spend and receive bills cannot be same");
-                            }
-                        
-                        // Make the current receive bill as visited.
-                        visited_serial.insert((receive_id, bill.serial), true) ;
-
-                        // If receive bill amount is greater than the 'total_spends', we return Err.
-                        if bill.amount > total_spends {
-                            return Err("Spending limit exceeded.");
+                    // If serial of spend or receive bill comes out to be same, identified by 'serial',
+                    // or a bill still in circulation already carries it, we return Err.
+                    if visited_serial.contains_key(&(spend_id, bill.serial)) || 
+                        visited_serial.contains_key(&(receive_id, bill.serial)) ||
+                        new_state.serial_in_use(bill.serial) {
+                            return Err(CashError::DuplicateSerial);
                         }
 
-                        // Update 'total_receives'.
-                        total_receives = total_receives.saturating_add(bill.amount) ;
+                    // Make the current receive bill as visited.
+                    visited_serial.insert((receive_id, bill.serial), true) ;
+
+                    // If receive bill is worth nothing, or less than the minimum bill, we return Err.
+                    if bill.amount == 0 {
+                        return Err(CashError::ZeroOutput) ;
+                    }
+                    if bill.amount < MIN_BILL {
+                        return Err(CashError::Dust) ;
+                    }
+
+                    // If receive bill amount is greater than the 'total_spends', we return Err.
+                    if bill.amount > total_spends {
+                        return Err(CashError::SpendingLimitExceeded);
+                    }
+
+                    // Update 'total_receives'.
+                    total_receives = total_receives.saturating_add(bill.amount) ;
+
+                    // Update 'total_spends'.
+                    total_spends = total_spends.saturating_sub(bill.amount) ;
+
+                    // Add received bill to the HashSet of current state.
+                    new_state.add_bill(bill.clone()) ;
+                }
 
-                        // Update 'total_spends'.
-                        total_spends = total_spends.saturating_sub(bill.amount) ;
+                Ok(new_state)
+            },
+            Tick => {
+                new_state.current_height = new_state.current_height.saturating_add(1) ;
+                let height = new_state.current_height() ;
+                let expired: Vec<Bill<A>> = new_state
+                    .bills
+                    .iter()
+                    .filter(|bill| bill.is_expired_at(height))
+                    .cloned()
+                    .collect() ;
+                for bill in &expired {
+                    new_state.remove_bill(bill) ;
+                }
+                Ok(new_state)
+            },
+            Split { serial, amounts } => {
+                let bill = match new_state.get_by_serial(*serial) {
+                    Some(bill) => bill.clone(),
+                    None => return Err(CashError::BillDoesNotExist),
+                } ;
+                if bill.is_expired_at(new_state.current_height()) {
+                    return Err(CashError::BillExpired) ;
+                }
 
-                        // Add received bill to the HashSet of current state.
-                        new_state.add_bill(bill.clone()) ;
+                // The new bills follow the same rules as the ones created by a transfer.
+                let mut total: u64 = 0 ;
+                for amount in amounts {
+                    if *amount == 0 {
+                        return Err(CashError::ZeroOutput) ;
+                    }
+                    if *amount < MIN_BILL {
+                        return Err(CashError::Dust) ;
                     }
-                    
-                    // If total_receives is zero after above checks, we return Err.
-                    if total_receives == 0 {
-                        return Err("Output of 0 value");
+                    total = total.saturating_add(*amount) ;
+                }
+                if total > bill.amount {
+                    return Err(CashError::SpendingLimitExceeded) ;
+                }
+
+                new_state.remove_bill(&bill) ;
+                for amount in amounts {
+                    while new_state.serial_in_use(new_state.next_serial()) {
+                        new_state.increment_serial() ;
                     }
+                    let change = Bill {
+                        owner: bill.owner,
+                        amount: *amount,
+                        serial: new_state.next_serial(),
+                        expiry: bill.expiry,
+                    } ;
+                    new_state.add_bill(change) ;
+                }
+                Ok(new_state)
+            },
+            SendBill { serial, new_owner } => {
+                let bill = match new_state.get_by_serial(*serial) {
+                    Some(bill) => bill.clone(),
+                    None => return Err(CashError::BillDoesNotExist),
+                } ;
+                if bill.is_expired_at(new_state.current_height()) {
+                    return Err(CashError::BillExpired) ;
+                }
 
-                    Ok(()) 
+                new_state.remove_bill(&bill) ;
+                while new_state.serial_in_use(new_state.next_serial()) {
+                    new_state.increment_serial() ;
+                }
+                let sent = Bill {
+                    owner: *new_owner,
+                    amount: bill.amount,
+                    serial: new_state.next_serial(),
+                    expiry: bill.expiry,
                 } ;
-                match transfer_process(&mut new_state) {
-                    Ok(_) => {
-                        return new_state;
-                    },
-                    Err(err) => {
-                        // For debug purpose.
-                        println!("{}", err.to_string()) ;
-                    },
+                new_state.add_bill(sent) ;
+                Ok(new_state)
+            },
+            NoOp => Ok(new_state),
+        }
+    }
+}
+
+/// Check that a batch of transactions, such as the ones a block author wants to include in
+/// a block, can all be applied one after the other on top of the given state.
+///
+/// The transactions are simulated in order. Besides each transaction having to be valid on
+/// its own, no transaction may spend a bill that an earlier one in the batch already spent.
+pub fn validate_batch<A: Account>(state: &State<A>, txs: &[CashTransaction<A>]) -> Result<(), CashError> {
+    let mut state = state.clone() ;
+    let mut spent: HashSet<Bill<A>> = HashSet::new() ;
+
+    for (tx_index, tx) in txs.iter().enumerate() {
+        match tx {
+            CashTransaction::Transfer { spends, .. } => {
+                if spends.iter().any(|bill| spent.contains(bill)) {
+                    return Err(CashError::DoubleSpend { tx_index }) ;
+                }
+                spent.extend(spends.iter().cloned()) ;
+            },
+            CashTransaction::Split { serial, .. } | CashTransaction::SendBill { serial, .. } => {
+                if spent.iter().any(|bill| bill.serial == *serial) {
+                    return Err(CashError::DoubleSpend { tx_index }) ;
+                }
+                // These consume the bill with the serial, so later transactions can't spend it.
+                if let Some(bill) = state.get_by_serial(*serial) {
+                    spent.insert(bill.clone()) ;
                 }
             },
+            _ => {},
         }
-        starting_state.clone()
+        state = DigitalCashSystem::try_next_state(&state, tx)? ;
+    }
+    Ok(())
+}
+
+/// Build a transfer that swaps all of `owner`'s bills for as few bills as possible, which is
+/// handy for a wallet that has collected a lot of small change.
+///
+/// The total is broken down greedily over the given denominations, largest first, skipping any
+/// below `MIN_BILL`. Whatever can't be expressed in them becomes one extra bill. If that
+/// remainder is dust, it is added to the last bill instead, so that the transfer stays valid.
+/// The new bills expire as soon as the earliest of the compacted bills would have.
+pub fn compact<A: Account>(state: &State<A>, owner: A, denominations: &[u64]) -> CashTransaction<A> {
+    let spends: Vec<Bill<A>> = state.bills.iter().filter(|bill| bill.owner == owner).cloned().collect() ;
+    let total = spends.iter().fold(0u64, |total, bill| total.saturating_add(bill.amount)) ;
+    let expiry = spends.iter().filter_map(|bill| bill.expiry).min() ;
+
+    let mut denominations: Vec<u64> = denominations.iter().copied().filter(|d| *d >= MIN_BILL).collect() ;
+    denominations.sort_unstable_by(|a, b| b.cmp(a)) ;
+
+    let mut amounts = Vec::new() ;
+    let mut remaining = total ;
+    for denomination in denominations {
+        while remaining >= denomination {
+            amounts.push(denomination) ;
+            remaining -= denomination ;
+        }
+    }
+    if remaining > 0 {
+        match amounts.last_mut() {
+            Some(last) if remaining < MIN_BILL => *last += remaining,
+            _ => amounts.push(remaining),
+        }
+    }
+
+    let mut serial = state.next_serial() ;
+    let mut receives = Vec::new() ;
+    for amount in amounts {
+        while state.serial_in_use(serial) {
+            serial += 1 ;
+        }
+        receives.push(Bill { owner, amount, serial, expiry }) ;
+        serial += 1 ;
+    }
+
+    CashTransaction::Transfer { spends, receives }
+}
+
+/// Merge two states into one that holds the bills of both, like reconciling two wallets.
+///
+/// The merge is rejected if any serial appears in both states, since serials have to stay
+/// unique. The merged state continues from the later of the two heights, so bills that have
+/// expired by then are left out. Its next serial is past every serial either state has used.
+pub fn merge<A: Account>(a: &State<A>, b: &State<A>) -> Result<State<A>, CashError> {
+    if a.serial_index.keys().any(|serial| b.serial_in_use(*serial)) {
+        return Err(CashError::DuplicateSerial) ;
+    }
+
+    let current_height = a.current_height.max(b.current_height) ;
+    let mut merged: State<A> = a.bills.iter()
+        .chain(b.bills.iter())
+        .filter(|bill| !bill.is_expired_at(current_height))
+        .cloned()
+        .collect() ;
+    merged.current_height = current_height ;
+    merged.set_serial(merged.next_serial().max(a.next_serial).max(b.next_serial)) ;
+    Ok(merged)
+}
+
+/// Sum up the bills of each owner, which turns the set of bills into account balances like the
+/// ones in the accounted currency. Users who don't own any bills are left out of the map.
+pub fn to_account_balances<A: Account>(state: &State<A>) -> HashMap<A, u64> {
+    let mut balances = HashMap::new() ;
+    for bill in &state.bills {
+        let balance = balances.entry(bill.owner).or_insert(0u64) ;
+        *balance = balance.saturating_add(bill.amount) ;
+    }
+    balances
+}
+
+/// The serials of the bills the owner can currently spend, in ascending order. This is all a
+/// wallet needs to look up the bills for a transfer. Bills that have already expired at the
+/// current height are left out, since spending them would be rejected.
+pub fn spendable_serials<A: Account>(state: &State<A>, owner: A) -> Vec<u64> {
+    let mut serials: Vec<u64> = state.bills.iter()
+        .filter(|bill| bill.owner == owner && !bill.is_expired_at(state.current_height()))
+        .map(|bill| bill.serial)
+        .collect() ;
+    serials.sort_unstable() ;
+    serials
+}
+
+/// We model this system as a state machine with seven possible transitions.
+impl<A: Account> StateMachine for DigitalCashSystem<A> {
+    type State = State<A>; 
+    type Transition = CashTransaction<A>;
+
+    /// An invalid transition leaves the state unchanged. Use `try_next_state` to find out why
+    /// it was rejected.
+    fn next_state(starting_state: &Self::State, transition: &Self::Transition) -> Self::State {
+        DigitalCashSystem::try_next_state(starting_state, transition).unwrap_or_else(|_| starting_state.clone())
     }
 }
 
@@ -219,92 +634,48 @@ fn sm_5_mint_new_cash() {
         },
     );
 
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 20, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_overflow_receives_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 42,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 42, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 42,
-                serial: 0,
-            }],
+            spends: vec![Bill::new(User::Alice, 42, 0)],
             receives: vec![
-                Bill {
-                    owner: User::Alice,
-                    amount: u64::MAX,
-                    serial: 1,
-                },
-                Bill {
-                    owner: User::Alice,
-                    amount: 42,
-                    serial: 2,
-                },
+                Bill::new(User::Alice, u64::MAX, 1),
+                Bill::new(User::Alice, 42, 2),
             ],
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 42,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 42, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_empty_spend_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
             spends: vec![],
-            receives: vec![Bill {
-                owner: User::Alice,
-                amount: 15,
-                serial: 1,
-            }],
+            receives: vec![Bill::new(User::Alice, 15, 1)],
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 20, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_empty_receive_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 20,
-                serial: 0,
-            }],
+            spends: vec![Bill::new(User::Alice, 20, 0)],
             receives: vec![],
         },
     );
@@ -315,353 +686,157 @@ fn sm_5_empty_receive_fails() {
 
 #[test]
 fn sm_5_output_value_0_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 20,
-                serial: 0,
-            }],
-            receives: vec![Bill {
-                owner: User::Bob,
-                amount: 0,
-                serial: 1,
-            }],
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 0, 1)],
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 20, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_serial_number_already_seen_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 20,
-                serial: 0,
-            }],
-            receives: vec![Bill {
-                owner: User::Alice,
-                amount: 18,
-                serial: 0,
-            }],
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Alice, 18, 0)],
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 20, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_spending_and_receiving_same_bill_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 20,
-                serial: 0,
-            }],
-            receives: vec![Bill {
-                owner: User::Alice,
-                amount: 20,
-                serial: 0,
-            }],
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Alice, 20, 0)],
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 20, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_receiving_bill_with_incorrect_serial_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 20,
-                serial: 0,
-            }],
+            spends: vec![Bill::new(User::Alice, 20, 0)],
             receives: vec![
-                Bill {
-                    owner: User::Alice,
-                    amount: 10,
-                    serial: u64::MAX,
-                },
-                Bill {
-                    owner: User::Bob,
-                    amount: 10,
-                    serial: 4000,
-                },
+                Bill::new(User::Alice, 10, u64::MAX),
+                Bill::new(User::Bob, 10, 4000),
             ],
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 20, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_spending_bill_with_incorrect_amount_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 40,
-                serial: 0,
-            }],
-            receives: vec![Bill {
-                owner: User::Bob,
-                amount: 40,
-                serial: 1,
-            }],
+            spends: vec![Bill::new(User::Alice, 40, 0)],
+            receives: vec![Bill::new(User::Bob, 40, 1)],
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 20, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_spending_same_bill_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 40,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 40, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
             spends: vec![
-                Bill {
-                    owner: User::Alice,
-                    amount: 40,
-                    serial: 0,
-                },
-                Bill {
-                    owner: User::Alice,
-                    amount: 40,
-                    serial: 0,
-                },
+                Bill::new(User::Alice, 40, 0),
+                Bill::new(User::Alice, 40, 0),
             ],
             receives: vec![
-                Bill {
-                    owner: User::Bob,
-                    amount: 20,
-                    serial: 1,
-                },
-                Bill {
-                    owner: User::Bob,
-                    amount: 20,
-                    serial: 2,
-                },
-                Bill {
-                    owner: User::Alice,
-                    amount: 40,
-                    serial: 3,
-                },
+                Bill::new(User::Bob, 20, 1),
+                Bill::new(User::Bob, 20, 2),
+                Bill::new(User::Alice, 40, 3),
             ],
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 40,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 40, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_spending_more_than_bill_fails() {
     let start = State::from([
-        Bill {
-            owner: User::Alice,
-            amount: 40,
-            serial: 0,
-        },
-        Bill {
-            owner: User::Charlie,
-            amount: 42,
-            serial: 1,
-        },
+        Bill::new(User::Alice, 40, 0),
+        Bill::new(User::Charlie, 42, 1),
     ]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
             spends: vec![
-                Bill {
-                    owner: User::Alice,
-                    amount: 40,
-                    serial: 0,
-                },
-                Bill {
-                    owner: User::Charlie,
-                    amount: 42,
-                    serial: 1,
-                },
+                Bill::new(User::Alice, 40, 0),
+                Bill::new(User::Charlie, 42, 1),
             ],
             receives: vec![
-                Bill {
-                    owner: User::Bob,
-                    amount: 20,
-                    serial: 2,
-                },
-                Bill {
-                    owner: User::Bob,
-                    amount: 20,
-                    serial: 3,
-                },
-                Bill {
-                    owner: User::Alice,
-                    amount: 52,
-                    serial: 4,
-                },
+                Bill::new(User::Bob, 20, 2),
+                Bill::new(User::Bob, 20, 3),
+                Bill::new(User::Alice, 52, 4),
             ],
         },
     );
     let expected = State::from([
-        Bill {
-            owner: User::Alice,
-            amount: 40,
-            serial: 0,
-        },
-        Bill {
-            owner: User::Charlie,
-            amount: 42,
-            serial: 1,
-        },
+        Bill::new(User::Alice, 40, 0),
+        Bill::new(User::Charlie, 42, 1),
     ]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_spending_non_existent_bill_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 32,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 32, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Bob,
-                amount: 1000,
-                serial: 32,
-            }],
-            receives: vec![Bill {
-                owner: User::Bob,
-                amount: 1000,
-                serial: 33,
-            }],
+            spends: vec![Bill::new(User::Bob, 1000, 32)],
+            receives: vec![Bill::new(User::Bob, 1000, 33)],
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 32,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 32, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_spending_from_alice_to_all() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 42,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 42, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 42,
-                serial: 0,
-            }],
+            spends: vec![Bill::new(User::Alice, 42, 0)],
             receives: vec![
-                Bill {
-                    owner: User::Alice,
-                    amount: 10,
-                    serial: 1,
-                },
-                Bill {
-                    owner: User::Bob,
-                    amount: 10,
-                    serial: 2,
-                },
-                Bill {
-                    owner: User::Charlie,
-                    amount: 10,
-                    serial: 3,
-                },
+                Bill::new(User::Alice, 10, 1),
+                Bill::new(User::Bob, 10, 2),
+                Bill::new(User::Charlie, 10, 3),
             ],
         },
     );
     let mut expected = State::from([
-        Bill {
-            owner: User::Alice,
-            amount: 10,
-            serial: 1,
-        },
-        Bill {
-            owner: User::Bob,
-            amount: 10,
-            serial: 2,
-        },
-        Bill {
-            owner: User::Charlie,
-            amount: 10,
-            serial: 3,
-        },
+        Bill::new(User::Alice, 10, 1),
+        Bill::new(User::Bob, 10, 2),
+        Bill::new(User::Charlie, 10, 3),
     ]);
     expected.set_serial(4);
     assert_eq!(end, expected);
@@ -669,54 +844,22 @@ fn sm_5_spending_from_alice_to_all() {
 
 #[test]
 fn sm_5_spending_from_bob_to_all() {
-    let start = State::from([Bill {
-        owner: User::Bob,
-        amount: 42,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Bob, 42, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Bob,
-                amount: 42,
-                serial: 0,
-            }],
+            spends: vec![Bill::new(User::Bob, 42, 0)],
             receives: vec![
-                Bill {
-                    owner: User::Alice,
-                    amount: 10,
-                    serial: 1,
-                },
-                Bill {
-                    owner: User::Bob,
-                    amount: 10,
-                    serial: 2,
-                },
-                Bill {
-                    owner: User::Charlie,
-                    amount: 22,
-                    serial: 3,
-                },
+                Bill::new(User::Alice, 10, 1),
+                Bill::new(User::Bob, 10, 2),
+                Bill::new(User::Charlie, 22, 3),
             ],
         },
     );
     let mut expected = State::from([
-        Bill {
-            owner: User::Alice,
-            amount: 10,
-            serial: 1,
-        },
-        Bill {
-            owner: User::Bob,
-            amount: 10,
-            serial: 2,
-        },
-        Bill {
-            owner: User::Charlie,
-            amount: 22,
-            serial: 3,
-        },
+        Bill::new(User::Alice, 10, 1),
+        Bill::new(User::Bob, 10, 2),
+        Bill::new(User::Charlie, 22, 3),
     ]);
     expected.set_serial(4);
     assert_eq!(end, expected);
@@ -725,67 +868,656 @@ fn sm_5_spending_from_bob_to_all() {
 #[test]
 fn sm_5_spending_from_charlie_to_all() {
     let mut start = State::from([
-        Bill {
-            owner: User::Charlie,
-            amount: 68,
-            serial: 54,
-        },
-        Bill {
-            owner: User::Alice,
-            amount: 4000,
-            serial: 58,
-        },
+        Bill::new(User::Charlie, 68, 54),
+        Bill::new(User::Alice, 4000, 58),
     ]);
     start.set_serial(59);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Charlie,
-                amount: 68,
-                serial: 54,
-            }],
+            spends: vec![Bill::new(User::Charlie, 68, 54)],
             receives: vec![
-                Bill {
-                    owner: User::Alice,
-                    amount: 42,
-                    serial: 59,
-                },
-                Bill {
-                    owner: User::Bob,
-                    amount: 5,
-                    serial: 60,
-                },
-                Bill {
-                    owner: User::Charlie,
-                    amount: 5,
-                    serial: 61,
-                },
+                Bill::new(User::Alice, 42, 59),
+                Bill::new(User::Bob, 5, 60),
+                Bill::new(User::Charlie, 5, 61),
             ],
         },
     );
     let mut expected = State::from([
-        Bill {
-            owner: User::Alice,
-            amount: 4000,
-            serial: 58,
-        },
-        Bill {
-            owner: User::Alice,
-            amount: 42,
-            serial: 59,
+        Bill::new(User::Alice, 4000, 58),
+        Bill::new(User::Alice, 42, 59),
+        Bill::new(User::Bob, 5, 60),
+        Bill::new(User::Charlie, 5, 61),
+    ]);
+    expected.set_serial(62);
+    assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_5_from_sparse_serials_mints_fresh_serial() {
+    let start = State::from([
+        Bill::new(User::Alice, 10, 7),
+        Bill::new(User::Bob, 20, 42),
+    ]);
+    assert_eq!(start.next_serial(), 43);
+
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Mint {
+            minter: User::Charlie,
+            amount: 5,
+        },
+    );
+    let expected = State::from([
+        Bill::new(User::Alice, 10, 7),
+        Bill::new(User::Bob, 20, 42),
+        Bill::new(User::Charlie, 5, 43),
+    ]);
+    assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_5_mint_skips_serial_already_in_use() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0)]);
+    // Force the next serial to collide with the existing bill.
+    start.set_serial(0);
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Mint {
+            minter: User::Bob,
+            amount: 10,
+        },
+    );
+    let expected = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 10, 1),
+    ]);
+    assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_5_get_bill_by_serial() {
+    let start = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 30, 5),
+    ]);
+
+    assert_eq!(
+        start.get_by_serial(5),
+        Some(&Bill::new(User::Bob, 30, 5))
+    );
+    assert_eq!(start.get_by_serial(1), None);
+}
+
+#[test]
+fn sm_5_spending_bill_identified_by_serial() {
+    let start = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 30, 5),
+    ]);
+
+    // Only the serial is known to the spender. The rest of the bill comes from the state.
+    let spent = start.get_by_serial(5).unwrap().clone();
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![spent],
+            receives: vec![Bill::new(User::Charlie, 30, 6)],
+        },
+    );
+    let expected = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Charlie, 30, 6),
+    ]);
+    assert_eq!(end, expected);
+    assert_eq!(end.get_by_serial(5), None);
+    assert_eq!(end.get_by_serial(6).map(|bill| bill.owner), Some(User::Charlie));
+}
+
+#[test]
+fn sm_5_display_summarizes_state() {
+    let state = State::from([
+        Bill::new(User::Alice, 5, 0),
+        Bill::new(User::Bob, 10, 1),
+        Bill::new(User::Charlie, 15, 3),
+    ]) ;
+
+    assert_eq!(state.to_string(), "State { bills: 3, total: 30, next_serial: 4 }") ;
+}
+
+#[test]
+fn sm_5_mint_zero_is_a_no_op() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Mint {
+            minter: User::Bob,
+            amount: 0,
+        },
+    );
+
+    assert_eq!(end, start);
+    assert_eq!(end.next_serial(), 1);
+}
+
+#[test]
+fn sm_5_validate_clean_batch() {
+    let start = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 10, 1),
+    ]);
+    let txs = [
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Charlie, 20, 2)],
+        },
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Bob, 10, 1)],
+            receives: vec![Bill::new(User::Alice, 10, 3)],
+        },
+        CashTransaction::Mint {
+            minter: User::Bob,
+            amount: 5,
+        },
+    ];
+
+    assert_eq!(validate_batch(&start, &txs), Ok(()));
+}
+
+#[test]
+fn sm_5_validate_double_spending_batch() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let spend = Bill::new(User::Alice, 20, 0);
+    let txs = [
+        CashTransaction::Transfer {
+            spends: vec![spend.clone()],
+            receives: vec![Bill::new(User::Bob, 20, 1)],
+        },
+        CashTransaction::Transfer {
+            spends: vec![spend],
+            receives: vec![Bill::new(User::Charlie, 20, 2)],
+        },
+    ];
+
+    assert_eq!(validate_batch(&start, &txs), Err(CashError::DoubleSpend { tx_index: 1 }));
+}
+
+#[test]
+fn sm_5_try_next_state_reports_error() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let result = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 25, 1)],
+        },
+    );
+
+    assert_eq!(result, Err(CashError::SpendingLimitExceeded));
+}
+
+#[test]
+fn sm_5_bill_expires_exactly_at_its_height() {
+    let bill = Bill {
+        owner: User::Alice,
+        amount: 20,
+        serial: 0,
+        expiry: Some(2),
+    } ;
+    let start = State::from([bill.clone()]) ;
+
+    let once = DigitalCashSystem::next_state(&start, &CashTransaction::Tick) ;
+    assert_eq!(once.current_height(), 1) ;
+    assert_eq!(once.get_by_serial(0), Some(&bill)) ;
+
+    let twice = DigitalCashSystem::next_state(&once, &CashTransaction::Tick) ;
+    assert_eq!(twice.current_height(), 2) ;
+    assert_eq!(twice.get_by_serial(0), None) ;
+}
+
+#[test]
+fn sm_5_expired_bill_cannot_be_spent() {
+    let bill = Bill {
+        owner: User::Alice,
+        amount: 20,
+        serial: 0,
+        expiry: Some(1),
+    } ;
+    let start = DigitalCashSystem::next_state(&State::from([bill.clone()]), &CashTransaction::Tick) ;
+    let result = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![bill],
+            receives: vec![Bill::new(User::Bob, 20, 1)],
+        },
+    ) ;
+
+    assert_eq!(result, Err(CashError::BillDoesNotExist)) ;
+}
+
+#[test]
+fn sm_5_spending_bill_past_expiry_is_rejected() {
+    // A hand-constructed state can still hold a bill past its expiry if no tick removed it.
+    let bill = Bill {
+        owner: User::Alice,
+        amount: 20,
+        serial: 0,
+        expiry: Some(0),
+    } ;
+    let start = State::from([bill.clone()]) ;
+    let result = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![bill],
+            receives: vec![Bill::new(User::Bob, 20, 1)],
+        },
+    ) ;
+
+    assert_eq!(result, Err(CashError::BillExpired)) ;
+}
+
+#[test]
+fn sm_5_tick_keeps_bills_without_expiry() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]) ;
+    let end = DigitalCashSystem::next_state(&start, &CashTransaction::Tick) ;
+
+    assert_eq!(end.current_height(), 1) ;
+    assert_eq!(end.bills, start.bills) ;
+}
+
+#[test]
+fn sm_5_sub_minimum_output_is_rejected() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]) ;
+    let result = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![
+                Bill::new(User::Bob, 20 - (MIN_BILL - 1), 1),
+                Bill::new(User::Alice, MIN_BILL - 1, 2),
+            ],
+        },
+    ) ;
+
+    assert_eq!(result, Err(CashError::Dust)) ;
+}
+
+#[test]
+fn sm_5_exactly_minimum_output_is_accepted() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]) ;
+    let end = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, MIN_BILL, 1)],
+        },
+    ) ;
+    let expected = State::from([Bill::new(User::Bob, MIN_BILL, 1)]) ;
+
+    assert_eq!(end, Ok(expected)) ;
+}
+
+#[test]
+fn sm_5_mint_below_minimum_is_rejected() {
+    let result = DigitalCashSystem::try_next_state(
+        &State::new(),
+        &CashTransaction::Mint {
+            minter: User::Alice,
+            amount: MIN_BILL - 1,
+        },
+    ) ;
+
+    assert_eq!(result, Err(CashError::Dust)) ;
+}
+
+#[test]
+fn sm_5_compact_merges_small_bills() {
+    let start = State::from_iter((0..10).map(|serial| Bill::new(User::Alice, 1, serial))) ;
+    let compaction = compact(&start, User::Alice, &[10, 5]) ;
+    let end = DigitalCashSystem::try_next_state(&start, &compaction).unwrap() ;
+
+    let alice_bills: Vec<&Bill> = end.bills.iter().filter(|bill| bill.owner == User::Alice).collect() ;
+    assert!(alice_bills.len() < 10) ;
+    assert_eq!(alice_bills.iter().map(|bill| bill.amount).sum::<u64>(), 10) ;
+}
+
+#[test]
+fn sm_5_compact_adds_dust_remainder_to_last_bill() {
+    let start = State::from([
+        Bill::new(User::Alice, 12, 0),
+        Bill::new(User::Bob, 7, 1),
+    ]) ;
+
+    match compact(&start, User::Alice, &[5]) {
+        CashTransaction::Transfer { spends, receives } => {
+            assert_eq!(spends.len(), 1) ;
+            let amounts: Vec<u64> = receives.iter().map(|bill| bill.amount).collect() ;
+            assert_eq!(amounts, vec![5, 7]) ;
+        },
+        _ => panic!("compaction should be a transfer"),
+    }
+}
+
+#[test]
+fn sm_5_account_balances_per_owner() {
+    let state = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 5, 1),
+        Bill {
+            owner: User::Alice,
+            amount: 15,
+            serial: 2,
+            expiry: Some(10),
+        },
+    ]) ;
+
+    assert_eq!(to_account_balances(&state), HashMap::from([(User::Alice, 35), (User::Bob, 5)])) ;
+}
+
+#[test]
+fn sm_5_ring_of_ten_accounts() {
+    use super::AccountId ;
+
+    let accounts: Vec<AccountId> = (0..10).map(AccountId).collect() ;
+    let mut state = State::from_iter(
+        accounts.iter().enumerate().map(|(serial, account)| Bill::new(*account, 10, serial as u64))
+    ) ;
+
+    // Every account hands its bill to the next one, and the last one back to the first.
+    for (index, sender) in accounts.iter().enumerate() {
+        let bill = state.bills.iter().find(|bill| bill.owner == *sender).cloned().unwrap() ;
+        let receiver = accounts[(index + 1) % accounts.len()] ;
+        let serial = state.next_serial() ;
+        state = DigitalCashSystem::next_state(
+            &state,
+            &CashTransaction::Transfer {
+                spends: vec![bill],
+                receives: vec![Bill::new(receiver, 10, serial)],
+            },
+        ) ;
+    }
+
+    assert_eq!(state.bills.len(), 10) ;
+    assert_eq!(to_account_balances(&state), accounts.iter().map(|account| (*account, 10)).collect()) ;
+}
+
+#[test]
+fn sm_5_split_into_exact_change() {
+    let start = State::from([Bill {
+        owner: User::Alice,
+        amount: 30,
+        serial: 0,
+        expiry: Some(8),
+    }]) ;
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Split {
+            serial: 0,
+            amounts: vec![10, 10, 10],
+        },
+    ) ;
+    let expected = State::from([
+        Bill {
+            owner: User::Alice,
+            amount: 10,
+            serial: 1,
+            expiry: Some(8),
+        },
+        Bill {
+            owner: User::Alice,
+            amount: 10,
+            serial: 2,
+            expiry: Some(8),
+        },
+        Bill {
+            owner: User::Alice,
+            amount: 10,
+            serial: 3,
+            expiry: Some(8),
+        },
+    ]) ;
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_5_over_split_is_rejected() {
+    let start = State::from([Bill::new(User::Alice, 30, 0)]) ;
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &start,
+            &CashTransaction::Split {
+                serial: 0,
+                amounts: vec![20, 20],
+            },
+        ),
+        Err(CashError::SpendingLimitExceeded)
+    ) ;
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &start,
+            &CashTransaction::Split {
+                serial: 1,
+                amounts: vec![10],
+            },
+        ),
+        Err(CashError::BillDoesNotExist)
+    ) ;
+}
+
+#[test]
+fn sm_5_noop_is_identity() {
+    let start = State::from([Bill {
+        owner: User::Alice,
+        amount: 20,
+        serial: 0,
+        expiry: Some(3),
+    }]) ;
+    assert_eq!(DigitalCashSystem::try_next_state(&start, &CashTransaction::NoOp), Ok(start.clone())) ;
+
+    // Padding a sequence with no-ops doesn't change where it ends up.
+    let padded = [CashTransaction::NoOp, CashTransaction::Tick, CashTransaction::NoOp] ;
+    let end = padded.iter().fold(start.clone(), |state, tx| DigitalCashSystem::next_state(&state, tx)) ;
+    assert_eq!(end, DigitalCashSystem::next_state(&start, &CashTransaction::Tick)) ;
+    assert_eq!(validate_batch(&start, &padded), Ok(())) ;
+}
+
+#[test]
+fn sm_5_mint_denominated_bills() {
+    let start = State::from([Bill::new(User::Bob, 20, 0)]) ;
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::MintDenominated {
+            minter: User::Alice,
+            denominations: vec![10, 5, 5],
         },
+    ) ;
+    let expected = State::from([
+        Bill::new(User::Bob, 20, 0),
+        Bill::new(User::Alice, 10, 1),
+        Bill::new(User::Alice, 5, 2),
+        Bill::new(User::Alice, 5, 3),
+    ]) ;
+    assert_eq!(end, expected) ;
+}
+
+#[test]
+fn sm_5_mint_denominated_rejects_zero_and_dust() {
+    let start = State::new() ;
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &start,
+            &CashTransaction::MintDenominated {
+                minter: User::Alice,
+                denominations: vec![10, 0],
+            },
+        ),
+        Err(CashError::ZeroOutput)
+    ) ;
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &start,
+            &CashTransaction::MintDenominated {
+                minter: User::Alice,
+                denominations: vec![10, 5, 1],
+            },
+        ),
+        Err(CashError::Dust)
+    ) ;
+}
+
+#[test]
+fn sm_5_merge_two_wallets() {
+    let alice = State::from([Bill::new(User::Alice, 20, 0)]) ;
+    let mut bob = State::from([
+        Bill::new(User::Bob, 10, 3),
+        Bill::new(User::Bob, 15, 7),
+    ]) ;
+    bob.set_serial(5) ;
+
+    let merged = merge(&alice, &bob).unwrap() ;
+    assert_eq!(merged.bills.len(), 3) ;
+    assert_eq!(merged.next_serial(), 8) ;
+    assert_eq!(to_account_balances(&merged), HashMap::from([(User::Alice, 20), (User::Bob, 25)])) ;
+    assert_eq!(merged.get_by_serial(7).map(|bill| bill.amount), Some(15)) ;
+}
+
+#[test]
+fn sm_5_merge_with_colliding_serial_fails() {
+    let alice = State::from([Bill::new(User::Alice, 20, 4)]) ;
+    let bob = State::from([Bill::new(User::Bob, 10, 4)]) ;
+
+    assert_eq!(merge(&alice, &bob), Err(CashError::DuplicateSerial)) ;
+    assert_eq!(merge(&alice, &alice), Err(CashError::DuplicateSerial)) ;
+}
+
+#[test]
+fn sm_5_send_bill_by_serial() {
+    let start = State::from([
+        Bill {
+            owner: User::Alice,
+            amount: 20,
+            serial: 0,
+            expiry: Some(8),
+        },
+        Bill::new(User::Alice, 5, 1),
+    ]) ;
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::SendBill {
+            serial: 0,
+            new_owner: User::Bob,
+        },
+    ) ;
+    let expected = State::from([
+        Bill::new(User::Alice, 5, 1),
         Bill {
             owner: User::Bob,
-            amount: 5,
-            serial: 60,
+            amount: 20,
+            serial: 2,
+            expiry: Some(8),
         },
+    ]) ;
+    assert_eq!(end, expected) ;
+
+    // The old serial is gone, so it can't be sent again.
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&end, &CashTransaction::SendBill { serial: 0, new_owner: User::Charlie }),
+        Err(CashError::BillDoesNotExist)
+    ) ;
+}
+
+#[test]
+fn sm_5_spendable_serials_of_owner() {
+    let mut state = State::from([
+        Bill::new(User::Alice, 20, 7),
+        Bill::new(User::Bob, 10, 2),
         Bill {
-            owner: User::Charlie,
+            owner: User::Alice,
             amount: 5,
-            serial: 61,
+            serial: 0,
+            expiry: Some(3),
         },
-    ]);
-    expected.set_serial(62);
-    assert_eq!(end, expected);
-}
\ No newline at end of file
+        Bill::new(User::Alice, 15, 4),
+    ]) ;
+
+    assert_eq!(spendable_serials(&state, User::Alice), vec![0, 4, 7]) ;
+    assert_eq!(spendable_serials(&state, User::Bob), vec![2]) ;
+    assert_eq!(spendable_serials(&state, User::Charlie), Vec::<u64>::new()) ;
+
+    // Once a bill has expired it can't be spent any more.
+    state.current_height = 3 ;
+    assert_eq!(spendable_serials(&state, User::Alice), vec![4, 7]) ;
+}
+
+#[test]
+fn sm_5_receive_reusing_live_serial_is_rejected() {
+    let start = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 30, 5),
+    ]) ;
+    let reuses_bobs_serial = CashTransaction::Transfer {
+        spends: vec![start.get_by_serial(0).unwrap().clone()],
+        receives: vec![Bill::new(User::Charlie, 20, 5)],
+    } ;
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &reuses_bobs_serial),
+        Err(CashError::DuplicateSerial)
+    ) ;
+
+    // Bob's bill stays reachable by its serial, so he can still spend it.
+    let end = DigitalCashSystem::next_state(&start, &reuses_bobs_serial) ;
+    assert_eq!(end, start) ;
+    assert_eq!(end.get_by_serial(5).map(|bill| bill.owner), Some(User::Bob)) ;
+}
+
+#[test]
+fn sm_5_try_from_bills_rejects_shared_serial() {
+    let alices_bill = Bill::new(User::Alice, 20, 3) ;
+    let bobs_bill = Bill::new(User::Bob, 30, 3) ;
+
+    assert_eq!(
+        State::try_from_bills([alices_bill.clone(), bobs_bill.clone()]),
+        Err(CashError::DuplicateSerial)
+    ) ;
+
+    // Bills with distinct serials build the same state as `State::from`.
+    let bobs_bill = Bill { serial: 4, ..bobs_bill } ;
+    assert_eq!(
+        State::try_from_bills([alices_bill.clone(), bobs_bill.clone()]),
+        Ok(State::from([alices_bill, bobs_bill]))
+    ) ;
+}
+
+#[test]
+#[should_panic(expected = "Two bills share a serial.")]
+fn sm_5_state_from_bills_sharing_a_serial_panics() {
+    let _ = State::from([
+        Bill::new(User::Alice, 20, 3),
+        Bill::new(User::Bob, 30, 3),
+    ]) ;
+}
+
+#[test]
+fn sm_5_batch_spending_a_split_or_sent_bill_again_is_double_spend() {
+    let start = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 10, 1),
+    ]) ;
+    let alices_bill = start.get_by_serial(0).unwrap().clone() ;
+
+    let split_then_transfer = [
+        CashTransaction::Split { serial: 0, amounts: vec![10, 10] },
+        CashTransaction::Transfer {
+            spends: vec![alices_bill],
+            receives: vec![Bill::new(User::Charlie, 20, 9)],
+        },
+    ] ;
+    assert_eq!(validate_batch(&start, &split_then_transfer), Err(CashError::DoubleSpend { tx_index: 1 })) ;
+
+    let send_then_split = [
+        CashTransaction::NoOp,
+        CashTransaction::SendBill { serial: 1, new_owner: User::Alice },
+        CashTransaction::Split { serial: 1, amounts: vec![5, 5] },
+    ] ;
+    assert_eq!(validate_batch(&start, &send_then_split), Err(CashError::DoubleSpend { tx_index: 2 })) ;
+}
@@ -0,0 +1,67 @@
+//! Traffic lights are a classic example of a state machine. The light steps through its colors
+//! in a fixed cycle, but an emergency vehicle can make it turn red right away.
+
+use super::StateMachine ;
+
+/// This state machine models a single traffic light.
+pub struct TrafficLight ;
+
+/// The color the light is currently showing.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum LightColor {
+    Red,
+    Green,
+    Yellow,
+}
+
+/// Something that can happen to the traffic light.
+pub enum LightEvent {
+    /// The timer fires and the light moves on to the next color in the cycle
+    /// Red -> Green -> Yellow -> Red.
+    Tick,
+    /// An emergency vehicle is approaching, so the light turns red no matter what it showed before.
+    Emergency,
+}
+
+impl StateMachine for TrafficLight {
+    type State = LightColor;
+    type Transition = LightEvent;
+
+    fn next_state(starting_state: &LightColor, transition: &LightEvent) -> LightColor {
+        use LightColor::* ;
+        use LightEvent::* ;
+
+        match (starting_state, transition) {
+            (_, Emergency) => Red,
+            (Red, Tick) => Green,
+            (Green, Tick) => Yellow,
+            (Yellow, Tick) => Red,
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn sm_6_tick_through_full_cycle() {
+    let mut light = LightColor::Red ;
+    let mut seen = vec![] ;
+    for _ in 0..3 {
+        light = TrafficLight::next_state(&light, &LightEvent::Tick) ;
+        seen.push(light) ;
+    }
+    assert_eq!(seen, vec![LightColor::Green, LightColor::Yellow, LightColor::Red]) ;
+}
+
+#[test]
+fn sm_6_emergency_turns_green_red() {
+    let start = LightColor::Green ;
+    let end = TrafficLight::next_state(&start, &LightEvent::Emergency) ;
+    assert_eq!(end, LightColor::Red) ;
+}
+
+#[test]
+fn sm_6_emergency_keeps_red() {
+    let start = LightColor::Red ;
+    let end = TrafficLight::next_state(&start, &LightEvent::Emergency) ;
+    assert_eq!(end, LightColor::Red) ;
+}
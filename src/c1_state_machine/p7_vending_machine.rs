@@ -0,0 +1,136 @@
+//! A vending machine takes coins, and hands out a snack once enough money has been inserted.
+//! Unlike the ATM, there is no authentication. Anybody can walk up and buy something, and anybody
+//! can ask for their coins back as long as they haven't bought anything yet.
+
+use super::StateMachine ;
+use std::collections::HashMap ;
+
+/// This state machine models a vending machine stocked with several kinds of items.
+pub struct VendingMachine ;
+
+/// Identifies one of the slots of items in the machine.
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub struct ItemId(pub u32) ;
+
+/// A slot of identical items, all sold at the same price.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Slot {
+    /// How much one item costs.
+    pub price: u64,
+    /// How many items are left in the slot.
+    pub count: u64,
+}
+
+/// The state of the vending machine.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Vending {
+    /// The value of the coins inserted so far, which haven't been spent or refunded.
+    pub inserted: u64,
+    /// The items in the machine, keyed by their slot.
+    pub stock: HashMap<ItemId, Slot>,
+    /// The item that came out of the machine on the last transition, if any.
+    pub last_dispensed: Option<ItemId>,
+    /// The coins that came out of the machine on the last transition, either as change or as a refund.
+    pub last_returned: u64,
+}
+
+impl Vending {
+    /// Create a machine with the given items and no coins in it.
+    pub fn new(stock: HashMap<ItemId, Slot>) -> Self {
+        Self { stock, ..Self::default() }
+    }
+}
+
+/// Something a customer can do at the vending machine.
+pub enum VendingAction {
+    /// Insert a coin of the given value.
+    InsertCoin(u64),
+    /// Choose an item. It is only dispensed if it is in stock and enough coins were inserted.
+    /// The rest of the inserted coins is returned as change.
+    SelectItem(ItemId),
+    /// Return all the inserted coins.
+    Refund,
+}
+
+impl StateMachine for VendingMachine {
+    type State = Vending;
+    type Transition = VendingAction;
+
+    fn next_state(starting_state: &Vending, transition: &VendingAction) -> Vending {
+        let mut new_state = starting_state.clone() ;
+        // Whatever came out on the previous transition has been collected by now.
+        new_state.last_dispensed = None ;
+        new_state.last_returned = 0 ;
+
+        match transition {
+            VendingAction::InsertCoin(value) => {
+                new_state.inserted = new_state.inserted.saturating_add(*value) ;
+            },
+            VendingAction::SelectItem(item) => {
+                // If the item is unknown, sold out, or too expensive, nothing happens and the
+                // customer can still insert more coins or pick something else.
+                let slot = match new_state.stock.get_mut(item) {
+                    Some(slot) if slot.count > 0 && slot.price <= new_state.inserted => slot,
+                    _ => return new_state,
+                } ;
+                slot.count -= 1 ;
+                new_state.last_dispensed = Some(*item) ;
+                new_state.last_returned = new_state.inserted - slot.price ;
+                new_state.inserted = 0 ;
+            },
+            VendingAction::Refund => {
+                new_state.last_returned = new_state.inserted ;
+                new_state.inserted = 0 ;
+            },
+        }
+        new_state
+    }
+}
+
+#[cfg(test)]
+fn stocked_machine() -> Vending {
+    Vending::new(HashMap::from([
+        (ItemId(0), Slot { price: 150, count: 2 }),
+        (ItemId(1), Slot { price: 100, count: 0 }),
+    ]))
+}
+
+#[test]
+fn sm_7_insufficient_funds() {
+    let start = VendingMachine::next_state(&stocked_machine(), &VendingAction::InsertCoin(100)) ;
+    let end = VendingMachine::next_state(&start, &VendingAction::SelectItem(ItemId(0))) ;
+
+    assert_eq!(end, start) ;
+}
+
+#[test]
+fn sm_7_out_of_stock() {
+    let start = VendingMachine::next_state(&stocked_machine(), &VendingAction::InsertCoin(200)) ;
+    let end = VendingMachine::next_state(&start, &VendingAction::SelectItem(ItemId(1))) ;
+
+    assert_eq!(end, start) ;
+}
+
+#[test]
+fn sm_7_purchase_with_change() {
+    let mut state = stocked_machine() ;
+    state = VendingMachine::next_state(&state, &VendingAction::InsertCoin(100)) ;
+    state = VendingMachine::next_state(&state, &VendingAction::InsertCoin(100)) ;
+    let end = VendingMachine::next_state(&state, &VendingAction::SelectItem(ItemId(0))) ;
+
+    assert_eq!(end.last_dispensed, Some(ItemId(0))) ;
+    assert_eq!(end.last_returned, 50) ;
+    assert_eq!(end.inserted, 0) ;
+    assert_eq!(end.stock[&ItemId(0)].count, 1) ;
+}
+
+#[test]
+fn sm_7_refund() {
+    let start = VendingMachine::next_state(&stocked_machine(), &VendingAction::InsertCoin(120)) ;
+    let end = VendingMachine::next_state(&start, &VendingAction::Refund) ;
+
+    assert_eq!(end.last_dispensed, None) ;
+    assert_eq!(end.last_returned, 120) ;
+    assert_eq!(end.inserted, 0) ;
+    assert_eq!(end.stock, start.stock) ;
+}
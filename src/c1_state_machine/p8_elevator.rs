@@ -0,0 +1,123 @@
+//! An elevator has to keep track of which floors people want to go to, and decide in which order
+//! to visit them. Our elevator is greedy: it always heads for the nearest floor that was requested.
+
+use super::StateMachine ;
+use std::collections::BTreeSet ;
+
+/// This state machine models a single elevator car.
+pub struct Elevator ;
+
+/// The state of the elevator.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ElevatorState {
+    /// The floor the car is currently at.
+    pub floor: u32,
+    /// The floors that were requested, but haven't been visited yet.
+    pub pending: BTreeSet<u32>,
+}
+
+/// Something that can happen to the elevator.
+pub enum ElevatorAction {
+    /// Somebody asks the elevator to visit the given floor. Asking for the floor the car is
+    /// already at does nothing.
+    RequestFloor(u32),
+    /// The car moves one floor toward the nearest pending request. When two requests are equally
+    /// near, the lower floor wins. Any requested floor the car arrives at is served. If the floor
+    /// the car is at was requested, it is served without moving. Without any pending requests,
+    /// the car stays where it is.
+    Step,
+}
+
+impl StateMachine for Elevator {
+    type State = ElevatorState;
+    type Transition = ElevatorAction;
+
+    fn next_state(starting_state: &ElevatorState, transition: &ElevatorAction) -> ElevatorState {
+        let mut new_state = starting_state.clone() ;
+
+        match transition {
+            ElevatorAction::RequestFloor(floor) => {
+                if *floor != new_state.floor {
+                    new_state.pending.insert(*floor) ;
+                }
+            },
+            ElevatorAction::Step => {
+                let current = new_state.floor ;
+                // The pending floors are visited in ascending order, so on a tie the lower floor is kept.
+                let target = match new_state.pending.iter().min_by_key(|floor| floor.abs_diff(current)) {
+                    Some(target) => *target,
+                    None => return new_state,
+                } ;
+                // The fields are public, so the current floor may have been requested. Serve it
+                // without moving.
+                if target == current {
+                    new_state.pending.remove(&current) ;
+                    return new_state ;
+                }
+                new_state.floor = if target < current { current - 1 } else { current + 1 } ;
+                new_state.pending.remove(&new_state.floor) ;
+            },
+        }
+        new_state
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn sm_8_serves_nearest_request_first() {
+    let mut state = ElevatorState { floor: 3, ..ElevatorState::default() } ;
+    state = Elevator::next_state(&state, &ElevatorAction::RequestFloor(7)) ;
+    state = Elevator::next_state(&state, &ElevatorAction::RequestFloor(1)) ;
+
+    let mut served = vec![] ;
+    for _ in 0..8 {
+        let before = state.pending.clone() ;
+        state = Elevator::next_state(&state, &ElevatorAction::Step) ;
+        if state.pending != before {
+            served.push(state.floor) ;
+        }
+    }
+
+    // Floor 1 is two floors away and floor 7 four, so the car goes down first, then up past 3.
+    assert_eq!(served, vec![1, 7]) ;
+    assert_eq!(state.floor, 7) ;
+    assert!(state.pending.is_empty()) ;
+}
+
+#[test]
+fn sm_8_serves_floor_passed_on_the_way() {
+    let mut state = ElevatorState::default() ;
+    state = Elevator::next_state(&state, &ElevatorAction::RequestFloor(3)) ;
+    state = Elevator::next_state(&state, &ElevatorAction::Step) ;
+    state = Elevator::next_state(&state, &ElevatorAction::RequestFloor(2)) ;
+    state = Elevator::next_state(&state, &ElevatorAction::Step) ;
+
+    assert_eq!(state.floor, 2) ;
+    assert_eq!(state.pending, BTreeSet::from([3])) ;
+}
+
+#[test]
+fn sm_8_idles_without_requests() {
+    let start = ElevatorState { floor: 4, ..ElevatorState::default() } ;
+    let end = Elevator::next_state(&start, &ElevatorAction::Step) ;
+
+    assert_eq!(end, start) ;
+}
+
+#[test]
+fn sm_8_request_current_floor_is_ignored() {
+    let start = ElevatorState { floor: 4, ..ElevatorState::default() } ;
+    let end = Elevator::next_state(&start, &ElevatorAction::RequestFloor(4)) ;
+
+    assert_eq!(end, start) ;
+}
+
+#[test]
+fn sm_8_pending_current_floor_is_served_in_place() {
+    for floor in [0, 5] {
+        let start = ElevatorState { floor, pending: BTreeSet::from([floor, floor + 3]) } ;
+        let end = Elevator::next_state(&start, &ElevatorAction::Step) ;
+
+        assert_eq!(end, ElevatorState { floor, pending: BTreeSet::from([floor + 3]) }) ;
+    }
+}
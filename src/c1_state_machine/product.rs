@@ -0,0 +1,65 @@
+//! State machines compose. Given two machines, we can build a bigger machine that runs both
+//! side by side. Its state holds one state of each machine, and each transition is meant for
+//! exactly one of them, leaving the other one as it was.
+
+use super::StateMachine;
+use std::marker::PhantomData;
+
+/// One of two alternatives. Used as the transition type of a `Product` to say which of the
+/// two machines the transition is for.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Either<L, R> {
+    Left(L),
+    Right(R),
+}
+
+/// Two state machines running in lockstep. The state is a pair of the two machines' states.
+pub struct Product<A: StateMachine, B: StateMachine>(PhantomData<(A, B)>);
+
+impl<A: StateMachine, B: StateMachine> StateMachine for Product<A, B>
+where
+    A::State: Clone,
+    B::State: Clone,
+{
+    type State = (A::State, B::State);
+    type Transition = Either<A::Transition, B::Transition>;
+
+    fn next_state(starting_state: &Self::State, transition: &Self::Transition) -> Self::State {
+        let (a, b) = starting_state;
+        match transition {
+            Either::Left(t) => (A::next_state(a, t), b.clone()),
+            Either::Right(t) => (a.clone(), B::next_state(b, t)),
+        }
+    }
+}
+
+#[cfg(test)]
+use super::p1_switches::LightSwitch;
+
+#[cfg(test)]
+#[test]
+fn sm_product_toggle_left_switch() {
+    type TwoLights = Product<LightSwitch, LightSwitch>;
+
+    let end = TwoLights::next_state(&(false, true), &Either::Left(()));
+    assert_eq!(end, (true, true));
+}
+
+#[test]
+fn sm_product_toggle_right_switch() {
+    type TwoLights = Product<LightSwitch, LightSwitch>;
+
+    let end = TwoLights::next_state(&(false, true), &Either::Right(()));
+    assert_eq!(end, (false, false));
+}
+
+#[test]
+fn sm_product_components_evolve_independently() {
+    type TwoLights = Product<LightSwitch, LightSwitch>;
+
+    let mut state = (false, false);
+    for transition in [Either::Left(()), Either::Left(()), Either::Right(()), Either::Left(())] {
+        state = TwoLights::next_state(&state, &transition);
+    }
+    assert_eq!(state, (true, true));
+}
@@ -0,0 +1,102 @@
+//! A small diagnostics tool that works with any state machine. A `Snapshotter` records every
+//! transition applied to a machine along with the state it produced. The recording can later be
+//! replayed from the initial state to check that the machine still reproduces it step for step.
+//!
+//! This is handy once machines get complicated enough that a bug, or some nondeterminism, can
+//! cause the same transitions to lead to different states.
+
+use super::StateMachine;
+
+/// Records the transitions applied to a state machine `M` and the state each one produced.
+pub struct Snapshotter<M: StateMachine> {
+    initial: M::State,
+    steps: Vec<(M::State, M::Transition)>,
+}
+
+impl<M: StateMachine> Snapshotter<M>
+where
+    M::State: Clone + PartialEq,
+{
+    /// Start a new recording from the given initial state.
+    pub fn new(initial: M::State) -> Self {
+        Self {
+            initial,
+            steps: Vec::new(),
+        }
+    }
+
+    /// The most recent recorded state, or the initial state if nothing was applied yet.
+    pub fn current(&self) -> &M::State {
+        self.steps
+            .last()
+            .map(|(state, _)| state)
+            .unwrap_or(&self.initial)
+    }
+
+    /// Apply the transition to the current state and record the result.
+    pub fn apply(&mut self, transition: M::Transition) -> &M::State {
+        let next = M::next_state(self.current(), &transition);
+        self.steps.push((next, transition));
+        self.current()
+    }
+
+    /// The number of recorded steps.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Whether no steps have been recorded yet.
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Replay every recorded transition from the initial state. Returns the final state if every
+    /// step reproduces the recorded state, or the index of the first step that diverges.
+    pub fn replay(&self) -> Result<M::State, usize> {
+        let mut state = self.initial.clone();
+        for (index, (recorded, transition)) in self.steps.iter().enumerate() {
+            state = M::next_state(&state, transition);
+            if state != *recorded {
+                return Err(index);
+            }
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+use super::p1_switches::LightSwitch;
+
+#[cfg(test)]
+#[test]
+fn sm_snapshot_replays_to_final_state() {
+    let mut recording = Snapshotter::<LightSwitch>::new(false);
+    recording.apply(());
+    recording.apply(());
+    recording.apply(());
+
+    assert_eq!(recording.len(), 3);
+    assert_eq!(recording.current(), &true);
+    assert_eq!(recording.replay(), Ok(true));
+}
+
+#[test]
+fn sm_snapshot_empty_recording_replays_to_initial_state() {
+    let recording = Snapshotter::<LightSwitch>::new(true);
+
+    assert!(recording.is_empty());
+    assert_eq!(recording.replay(), Ok(true));
+}
+
+#[test]
+fn sm_snapshot_reports_diverging_step() {
+    let mut recording = Snapshotter::<LightSwitch>::new(false);
+    recording.apply(());
+    recording.apply(());
+    recording.apply(());
+
+    // Corrupt the state recorded after the second transition.
+    recording.steps[1].0 = true;
+
+    assert_eq!(recording.replay(), Err(1));
+}
@@ -5,6 +5,9 @@
 mod p1_header_chain;
 mod p2_extrinsic_state;
 mod p3_consensus;
-mod p4_batched_extrinsics;
-mod p5_fork_choice;
-mod p6_rich_state;
\ No newline at end of file
+pub mod p4_batched_extrinsics;
+pub mod p5_fork_choice;
+pub mod p6_rich_state;
+mod p7_proof_of_authority;
+mod p8_blockchain;
+mod p9_signed_extrinsics;
\ No newline at end of file
@@ -33,10 +33,11 @@ impl Header {
     }
 
     /// Create a return a new valid child header.
+    /// The height saturates at `u64::MAX` instead of overflowing.
     fn child(&self) -> Self {
         Self {
             parent: hash(self),
-            height: self.height + 1,
+            height: self.height.saturating_add(1),
             extrinsics_root: (),
             state_root: (),
             consensus_digest: (),
@@ -166,3 +167,13 @@ fn bc_1_invalid_chain_is_really_invalid() {
     let invalid_chain = build_an_invalid_chain();
     assert!(!invalid_chain[0].verify_sub_chain(&invalid_chain[1..]));
 }
+
+#[test]
+fn bc_1_child_height_saturates() {
+    let mut h = Header::genesis();
+    h.height = u64::MAX;
+
+    let child = h.child();
+    assert_eq!(child.height, u64::MAX);
+    assert_eq!(child.parent, hash(&h));
+}
@@ -24,20 +24,27 @@ pub struct Header {
 impl Header {
     /// Returns a new valid header.
     fn genesis() -> Self {
+        Self::genesis_with(0)
+    }
+
+    /// Returns a new valid genesis header whose state starts from the given baseline
+    /// rather than zero. Children keep adding their extrinsics on top of it.
+    fn genesis_with(initial_state: u64) -> Self {
         Self {
             parent: 0,
             height: 0,
             extrinsic: 0,
-            state: 0,
+            state: initial_state,
             consensus_digest: (),
         }
     }
 
     /// Create and return a valid child header.
+    /// The height saturates at `u64::MAX` instead of overflowing.
     fn child(&self, extrinsic: u64) -> Self {
         Self {
             parent: hash(self),
-            height: self.height + 1,
+            height: self.height.saturating_add(1),
             extrinsic,
             state: self.state + extrinsic,
             consensus_digest: (), 
@@ -68,10 +75,42 @@ impl Header {
         }
         is_verified
     }
+
+    /// Verify the chain like `verify_sub_chain`, but also require every block to carry a
+    /// non-zero extrinsic. This models chains that forbid no-op blocks, so the state strictly
+    /// increases from block to block.
+    fn verify_sub_chain_strict(&self, chain: &[Header]) -> bool {
+        self.verify_sub_chain(chain) && chain.iter().all(|header| header.extrinsic != 0)
+    }
 }
 
 // And finally a few functions to use the code we just
 
+/// Look up the state of the chain at the given height, as committed to by the header there.
+/// Since every header carries the running state, no extrinsics need to be re-executed. Returns
+/// `None` if the chain doesn't cover the height.
+///
+/// The chain is assumed to be valid, so heights increase by one from header to header.
+fn state_at(chain: &[Header], height: u64) -> Option<u64> {
+    let first_height = chain.first()?.height;
+    let index = height.checked_sub(first_height)?;
+    chain.get(usize::try_from(index).ok()?).map(|header| header.state)
+}
+
+/// Build a fork of the given chain. The returned chain shares every header of `base`, and then
+/// continues from its last header with one new block per divergent extrinsic. Forking the same
+/// base twice with different extrinsics gives two sibling chains. An empty base forks from
+/// genesis.
+fn fork_at(base: &[Header], divergent_extrinsics: &[u64]) -> Vec<Header> {
+    let mut chain = if base.is_empty() { vec![Header::genesis()] } else { base.to_vec() } ;
+
+    for extrinsic in divergent_extrinsics {
+        let child = chain.last().expect("the chain has at least one header").child(*extrinsic) ;
+        chain.push(child) ;
+    }
+    chain
+}
+
 /// Build and return a valid chain with the given number of blocks.
 fn build_valid_chain(n: u64) -> Vec<Header> {
     let g = Header::genesis() ;
@@ -120,14 +159,9 @@ fn build_an_invalid_chain() -> Vec<Header> {
 ///
 /// Side question: What is the fewest number of headers you could create to achieve this goal.
 fn build_forked_chain() -> (Vec<Header>, Vec<Header>) {
-    let g = Header::genesis() ;
-    let b1 = g.child(1) ;
-    let b2 = b1.child(2) ;
-    let b3 = b2.child(3) ;
-
-    let b3_prime = b2.child(4) ;
+    let shared = fork_at(&[], &[1, 2]) ;
 
-    (vec![g.clone(), b1.clone(), b2.clone(), b3], vec![g, b1, b2, b3_prime])
+    (fork_at(&shared, &[3]), fork_at(&shared, &[4]))
 }
 
 #[cfg(test)]
@@ -255,4 +289,72 @@ fn bc_2_verify_forked_chain() {
     // Is that enough? Is it possible that the two chains have the same final block,
     // but differ somewhere else?
     assert_ne!(c1.last(), c2.last());
-}
\ No newline at end of file
+}
+
+#[test]
+fn bc_2_verify_chain_from_nonzero_genesis_state() {
+    let g = Header::genesis_with(100);
+    let b1 = g.child(5);
+    let b2 = b1.child(6);
+
+    assert_eq!(g.state, 100);
+    assert_eq!(b1.state, 105);
+    assert_eq!(b2.state, 111);
+    assert!(g.verify_sub_chain(&[b1, b2]));
+}
+
+#[test]
+fn bc_2_state_at_height() {
+    let g = Header::genesis();
+    let b1 = g.child(5);
+    let b2 = b1.child(3);
+    let b3 = b2.child(10);
+    let chain = vec![g, b1, b2, b3];
+
+    assert_eq!(state_at(&chain, 0), Some(0));
+    assert_eq!(state_at(&chain, 1), Some(5));
+    assert_eq!(state_at(&chain, 2), Some(8));
+    assert_eq!(state_at(&chain, 3), Some(18));
+    assert_eq!(state_at(&chain, 4), None);
+
+    // A sub-chain only knows about the heights it covers.
+    assert_eq!(state_at(&chain[2..], 1), None);
+    assert_eq!(state_at(&chain[2..], 3), Some(18));
+    assert_eq!(state_at(&[], 0), None);
+}
+
+#[test]
+fn bc_2_strict_verify_rejects_zero_extrinsic() {
+    let g = Header::genesis();
+    let b1 = g.child(5);
+    let b2 = b1.child(0);
+    let b3 = b2.child(6);
+
+    assert!(g.verify_sub_chain(&[b1.clone(), b2.clone(), b3.clone()]));
+    assert!(!g.verify_sub_chain_strict(&[b1.clone(), b2, b3]));
+    assert!(g.verify_sub_chain_strict(&[b1]));
+}
+
+
+#[test]
+fn bc_2_fork_at_shares_prefix() {
+    let base = fork_at(&[], &[5, 6]);
+    let original = fork_at(&base, &[1, 2]);
+    let fork = fork_at(&base, &[3, 4]);
+
+    assert_eq!(&original[..3], &base[..]);
+    assert_eq!(&fork[..3], &base[..]);
+    assert_ne!(original[3], fork[3]);
+    assert_eq!(fork[3].parent, hash(&base[2]));
+    assert_eq!(fork[4].state, 18);
+
+    assert!(base[0].verify_sub_chain(&original[1..]));
+    assert!(base[0].verify_sub_chain(&fork[1..]));
+}
+
+#[test]
+fn bc_2_strict_verify_empty_chain() {
+    let g = Header::genesis();
+
+    assert!(g.verify_sub_chain_strict(&[]));
+}
@@ -6,6 +6,7 @@
 
 use crate::hash;
 use rand::Rng;
+use std::collections::HashSet;
 
 // We will use Rust's built-in hashing where the output type is u64. I'll make an alias
 // so that the code is slightly more readable.
@@ -31,18 +32,38 @@ pub struct Header {
     extrinsic: u64,
     state: u64,
     consensus_digest: u64,
+    // The PoW threshold every block of this chain has to get its hash below. It is chosen at
+    // genesis and every child inherits it from its parent.
+    threshold: u64,
 }
 
 // Here are the methods for creating new header and verifying headers.
 impl Header {
     /// Returns a new valid genesis header.
     fn genesis() -> Self {
+        Self::genesis_with(0)
+    }
+
+    /// Returns a new valid genesis header whose state starts from the given baseline
+    /// rather than zero. The chain is mined against the default `THRESHOLD`, and a different
+    /// baseline gives a different genesis hash, so its blocks never link up with other chains.
+    fn genesis_with(initial_state: u64) -> Self {
         Self {
             parent: Hash::default(),
             height: 0,
             extrinsic: 0,
-            state: 0,
+            state: initial_state,
             consensus_digest: 0,
+            threshold: THRESHOLD,
+        }
+    }
+
+    /// Returns a new valid genesis header for a chain mined at the given difficulty rather
+    /// than the default `THRESHOLD`. A lower threshold makes the chain harder to mine.
+    fn genesis_with_threshold(threshold: u64) -> Self {
+        Self {
+            threshold,
+            ..Self::genesis()
         }
     }
 
@@ -52,20 +73,26 @@ impl Header {
         return range.gen::<u32>() as u64;
     }
 
-    /// Create and return a valid child header.
+    /// Create and return a valid child header, mined against the chain's threshold.
+    /// The height saturates at `u64::MAX` instead of overflowing.
+    ///
+    /// Verification only accepts a child mined against its parent's threshold, so the threshold
+    /// is set once for the whole chain. Tests can start a chain from a lenient genesis with
+    /// `genesis_with_threshold` to mine quickly.
     fn child(&self, extrinsic: u64) -> Self {
         let mut valid_child_header = Self {
             parent: hash(self),
-            height: self.height + 1,
+            height: self.height.saturating_add(1),
             extrinsic,
             state: self.state + extrinsic,
             consensus_digest: Hash::default(),
+            threshold: self.threshold,
         };
 
         loop {
             let nonce = self.generate_nonce();
             valid_child_header.consensus_digest = nonce;
-            if hash(&valid_child_header) < THRESHOLD {
+            if hash(&valid_child_header) < valid_child_header.threshold {
                 return valid_child_header;
             }
         }
@@ -76,22 +103,21 @@ impl Header {
     /// In addition to all the rules we had before, we now need to check that the block hash
     /// is below a specific threshold.
     fn verify_sub_chain(&self, chain: &[Header]) -> bool {
+        self.verify_sub_chain_with(&PowEngine, chain)
+    }
+
+    /// Verify that all the given headers form a valid chain from this header to the tip,
+    /// according to the validity rules of the given consensus engine.
+    fn verify_sub_chain_with<E: ConsensusEngine>(&self, engine: &E, chain: &[Header]) -> bool {
         let mut prev_header = self;
-        let mut prev_header_height = self.height;
-        let mut chain_iter = chain.iter();
-        let mut is_verified = true;
 
-        while let Some(header) = chain_iter.next() {
-            if prev_header_height.saturating_add(1) != header.height {
+        for header in chain {
+            if !engine.verify_header(prev_header, header) {
                 return false;
             }
-            is_verified &= header.parent == hash(prev_header)
-                && header.state == prev_header.state + header.extrinsic
-                && hash(header) < THRESHOLD;
             prev_header = header;
-            prev_header_height = header.height;
         }
-        is_verified
+        true
     }
 
     // After the blockchain ran for a while, a political rift formed in the community.
@@ -103,53 +129,130 @@ impl Header {
     /// Verify that the given headers form a valid chain.
     /// In this case, "valid" means that the STATE MUST BE EVEN.
     fn verify_sub_chain_even(&self, chain: &[Header]) -> bool {
-        let mut prev_header = self;
-        let mut prev_header_height = self.height;
-        let mut chain_iter = chain.iter();
-        let mut is_verified = true;
-
-        while let Some(header) = chain_iter.next() {
-            if prev_header_height.saturating_add(1) != header.height {
-                return false;
-            }
-
-            if header.height > FORK_HEIGHT && header.state % 2 != 0 {
-                return false;
-            }
-
-            is_verified &= header.parent == hash(prev_header)
-                && header.state == prev_header.state + header.extrinsic
-                && hash(header) < THRESHOLD;
-            prev_header = header;
-            prev_header_height = header.height;
-        }
-        is_verified
+        self.verify_sub_chain_with(&EvenStateEngine, chain)
     }
 
     /// Verify that the given headers form a valid chain.
     /// In this case, "valid" means that the STATE MUST BE ODD.
     fn verify_sub_chain_odd(&self, chain: &[Header]) -> bool {
-        let mut prev_header = self;
-        let mut prev_header_height = self.height;
-        let mut chain_iter = chain.iter();
-        let mut is_verified = true;
+        self.verify_sub_chain_with(&OddStateEngine, chain)
+    }
 
-        while let Some(header) = chain_iter.next() {
-            if prev_header_height.saturating_add(1) != header.height {
-                return false;
-            }
+    /// Verify that the given headers form a valid chain.
+    /// In this case, "valid" means that NO BLOCK MAY BE A NO-OP, so the state strictly increases.
+    fn verify_sub_chain_strict(&self, chain: &[Header]) -> bool {
+        self.verify_sub_chain_with(&StrictEngine, chain)
+    }
 
-            if header.height > FORK_HEIGHT && header.state % 2 == 0 {
-                return false;
-            }
+    /// Verify that the given headers form a valid chain.
+    /// In this case, "valid" also means that NO TWO HEADERS IN THE CHAIN SHARE A NONCE.
+    ///
+    /// This rule is contrived. Reusing a nonce is harmless, because the nonce only has to work
+    /// for the header it is in. It does show that a validity rule can look at the chain as a
+    /// whole, which a `ConsensusEngine` that only sees a parent and a child can't express.
+    fn verify_sub_chain_unique_nonces(&self, chain: &[Header]) -> bool {
+        let mut seen_nonces = HashSet::new();
+        self.verify_sub_chain(chain) && chain.iter().all(|header| seen_nonces.insert(header.consensus_digest))
+    }
+}
 
-            is_verified &= header.parent == hash(prev_header)
-                && header.state == prev_header.state + header.extrinsic
-                && hash(header) < THRESHOLD;
-            prev_header = header;
-            prev_header_height = header.height;
+/// The validity rules a chain follows, separated from the chain itself.
+///
+/// Each side of the contentious fork above agrees on how headers are built, but not on which
+/// headers are valid. Rather than writing a dedicated verification method for every side, we
+/// capture each side's rules in an engine and verify the chain with whichever engine we follow.
+/// A Proof of Authority engine would check a signature here instead of the PoW threshold.
+pub trait ConsensusEngine {
+    /// Verify a single header given its parent.
+    fn verify_header(&self, parent: &Header, child: &Header) -> bool;
+}
+
+/// The original rules: consecutive heights, linked hashes, the adder state, and PoW against
+/// the threshold the chain was started with.
+pub struct PowEngine;
+
+impl ConsensusEngine for PowEngine {
+    fn verify_header(&self, parent: &Header, child: &Header) -> bool {
+        parent.height.saturating_add(1) == child.height
+            && child.parent == hash(parent)
+            && child.state == parent.state + child.extrinsic
+            && child.threshold == parent.threshold
+            && hash(child) < child.threshold
+    }
+}
+
+/// The original rules, plus every state after the fork height must be even.
+pub struct EvenStateEngine;
+
+impl ConsensusEngine for EvenStateEngine {
+    fn verify_header(&self, parent: &Header, child: &Header) -> bool {
+        PowEngine.verify_header(parent, child) && (child.height <= FORK_HEIGHT || child.state % 2 == 0)
+    }
+}
+
+/// The original rules, plus every state after the fork height must be odd.
+pub struct OddStateEngine;
+
+impl ConsensusEngine for OddStateEngine {
+    fn verify_header(&self, parent: &Header, child: &Header) -> bool {
+        PowEngine.verify_header(parent, child) && (child.height <= FORK_HEIGHT || child.state % 2 != 0)
+    }
+}
+
+/// The original rules, plus every block must carry a non-zero extrinsic.
+pub struct StrictEngine;
+
+impl ConsensusEngine for StrictEngine {
+    fn verify_header(&self, parent: &Header, child: &Header) -> bool {
+        PowEngine.verify_header(parent, child) && child.extrinsic != 0
+    }
+}
+
+/// Look up the state of the chain at the given height, as committed to by the header there.
+/// Returns `None` if the chain doesn't cover the height.
+///
+/// Nothing is re-executed or re-mined, so this is only meaningful on a chain that has already
+/// been verified, under whichever consensus rules the caller follows. After a contentious fork
+/// the answer depends on which side of the fork the chain is on.
+///
+/// The chain is assumed to be valid, so heights increase by one from header to header.
+fn state_at(chain: &[Header], height: u64) -> Option<u64> {
+    let first_height = chain.first()?.height;
+    let index = height.checked_sub(first_height)?;
+    chain.get(usize::try_from(index).ok()?).map(|header| header.state)
+}
+
+/// The height of the first header at which the two chains differ. Returns `None` if they
+/// agree on every height they both cover, for example when one is a prefix of the other.
+///
+/// Both chains are assumed to start at the same height.
+fn divergence_height(a: &[Header], b: &[Header]) -> Option<u64> {
+    a.iter().zip(b).find(|(header_a, header_b)| header_a != header_b).map(|(header_a, _)| header_a.height)
+}
+
+/// Create an invalid child header of the given header. The returned header is properly linked
+/// to its parent, has the right height and is mined below the PoW threshold, but its state
+/// is not the parent's state plus its extrinsic.
+///
+/// Anyone only checking the links and the proof of work would accept this header, which is why
+/// verification has to execute the extrinsic as well.
+fn build_invalid_child_block_with_valid_header(parent: &Header) -> Header {
+    let extrinsic = 5;
+    let mut invalid_child_header = Header {
+        parent: hash(parent),
+        height: parent.height.saturating_add(1),
+        extrinsic,
+        // The correct state would be `parent.state + extrinsic`.
+        state: parent.state + extrinsic + 1,
+        consensus_digest: Hash::default(),
+        threshold: parent.threshold,
+    };
+
+    loop {
+        invalid_child_header.consensus_digest = parent.generate_nonce();
+        if hash(&invalid_child_header) < invalid_child_header.threshold {
+            return invalid_child_header;
         }
-        is_verified
     }
 }
 
@@ -403,4 +506,205 @@ fn bc_3_verify_forked_chain() {
     // Only the odd chain is valid according to the odd rules
     assert!(!g.verify_sub_chain_odd(&full_even_chain[..]));
     assert!(g.verify_sub_chain_odd(&full_odd_chain[..]));
+
+    // The chains split on the first block after the fork height.
+    assert_eq!(divergence_height(&full_even_chain, &full_odd_chain), Some(FORK_HEIGHT + 1));
+}
+
+#[test]
+fn bc_3_verify_chain_from_nonzero_genesis_state() {
+    let g = Header::genesis_with(100);
+    let b1 = g.child(5);
+    let b2 = b1.child(6);
+
+    assert_eq!(b2.state, 111);
+    assert!(hash(&b1) < THRESHOLD && hash(&b2) < THRESHOLD);
+    assert!(g.verify_sub_chain(&[b1.clone(), b2.clone()]));
+
+    // The mined blocks are tied to this genesis, so they don't extend the usual one.
+    assert_ne!(hash(&g), hash(&Header::genesis()));
+    assert!(!Header::genesis().verify_sub_chain(&[b1, b2]));
+}
+
+#[test]
+fn bc_3_same_chain_under_multiple_engines() {
+    let (prefix, even, odd) = build_contentious_forked_chain();
+
+    let g = &prefix[0];
+    let full_even_chain = [&prefix[1..], &even].concat();
+    let full_odd_chain = [&prefix[1..], &odd].concat();
+
+    assert!(g.verify_sub_chain_with(&PowEngine, &full_even_chain));
+    assert!(g.verify_sub_chain_with(&EvenStateEngine, &full_even_chain));
+    assert!(!g.verify_sub_chain_with(&OddStateEngine, &full_even_chain));
+
+    assert!(g.verify_sub_chain_with(&PowEngine, &full_odd_chain));
+    assert!(!g.verify_sub_chain_with(&EvenStateEngine, &full_odd_chain));
+    assert!(g.verify_sub_chain_with(&OddStateEngine, &full_odd_chain));
+
+    assert_eq!(divergence_height(&full_even_chain, &full_odd_chain), Some(FORK_HEIGHT + 1));
+
+    // Before the fork height every engine agrees.
+    assert!(g.verify_sub_chain_with(&EvenStateEngine, &prefix[1..]));
+    assert!(g.verify_sub_chain_with(&OddStateEngine, &prefix[1..]));
+}
+
+#[test]
+fn bc_3_student_invalid_block_really_is_invalid() {
+    let g = Header::genesis();
+    let b1 = g.child(1);
+    let b2 = build_invalid_child_block_with_valid_header(&b1);
+
+    // The links and the proof of work all check out.
+    assert_eq!(b2.height, b1.height + 1);
+    assert_eq!(b2.parent, hash(&b1));
+    assert!(hash(&b2) < THRESHOLD);
+
+    // But the state doesn't, so the chain is rejected.
+    assert_ne!(b2.state, b1.state + b2.extrinsic);
+    assert!(!g.verify_sub_chain(&[b1, b2]));
+}
+
+#[test]
+fn bc_3_state_at_height_on_both_sides_of_a_fork() {
+    let (prefix, even, odd) = build_contentious_forked_chain();
+    let full_even_chain = [&prefix[..], &even].concat();
+    let full_odd_chain = [&prefix[..], &odd].concat();
+
+    // Up to the fork height both sides agree on the history.
+    for height in 0..=FORK_HEIGHT {
+        assert_eq!(state_at(&full_even_chain, height), state_at(&full_odd_chain, height));
+    }
+
+    // After it, each side reports the states its own rules allowed.
+    let tip_height = full_even_chain.last().unwrap().height;
+    for height in FORK_HEIGHT + 1..=tip_height {
+        assert_eq!(state_at(&full_even_chain, height).map(|state| state % 2), Some(0));
+        assert_eq!(state_at(&full_odd_chain, height).map(|state| state % 2), Some(1));
+    }
+    assert_eq!(state_at(&full_even_chain, tip_height + 1), None);
+
+    // The suffixes alone only know about the heights after the fork.
+    assert_eq!(state_at(&even, FORK_HEIGHT), None);
+    assert_eq!(state_at(&odd, FORK_HEIGHT + 1), state_at(&full_odd_chain, FORK_HEIGHT + 1));
+}
+
+#[test]
+fn bc_3_chains_mined_at_different_difficulties() {
+    let easy_threshold = u64::MAX / 2;
+    let hard_threshold = u64::MAX / 1000;
+
+    let easy_g = Header::genesis_with_threshold(easy_threshold);
+    let easy_b1 = easy_g.child(1);
+    let easy_b2 = easy_b1.child(2);
+    assert!(easy_g.verify_sub_chain(&[easy_b1, easy_b2]));
+
+    let hard_g = Header::genesis_with_threshold(hard_threshold);
+    let hard_b1 = hard_g.child(1);
+    let hard_b2 = hard_b1.child(2);
+    assert!(hash(&hard_b1) < hard_threshold);
+    assert!(hash(&hard_b2) < hard_threshold);
+    assert!(hard_g.verify_sub_chain(&[hard_b1, hard_b2]));
+}
+
+#[test]
+fn bc_3_child_cant_lower_its_difficulty() {
+    let g = Header::genesis_with_threshold(u64::MAX / 1000);
+    let mut b1 = g.child(1);
+
+    // Claim a trivially easy threshold, which any hash would satisfy.
+    b1.threshold = u64::MAX;
+    assert!(!g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_3_divergence_height_after_shared_prefix() {
+    let g = Header::genesis();
+    let b1 = g.child(1);
+    let b2_a = b1.child(2);
+    let b2_b = b1.child(3);
+
+    let chain_a = [g.clone(), b1.clone(), b2_a.clone(), b2_a.child(4)];
+    let chain_b = [g.clone(), b1.clone(), b2_b];
+
+    assert_eq!(divergence_height(&chain_a, &chain_b), Some(2));
+    assert_eq!(divergence_height(&chain_a, &chain_a[..2]), None);
+}
+
+#[test]
+fn bc_3_strict_verify_rejects_zero_extrinsic() {
+    let g = Header::genesis();
+    let b1 = g.child(5);
+    // Mining an empty block takes just as much work as any other.
+    let empty = b1.child(0);
+    assert!(hash(&empty) < THRESHOLD);
+
+    assert!(g.verify_sub_chain(&[b1.clone(), empty.clone()]));
+    assert!(!g.verify_sub_chain_strict(&[b1.clone(), empty]));
+    assert!(g.verify_sub_chain_strict(&[b1]));
+
+    // The strict rules still include the proof of work.
+    let mut unmined = g.child(5);
+    while hash(&unmined) < THRESHOLD {
+        unmined.consensus_digest += 1;
+    }
+    assert!(!g.verify_sub_chain_strict(&[unmined]));
+}
+
+#[test]
+fn bc_3_empty_chain_is_valid_under_every_engine() {
+    let g = Header::genesis();
+
+    assert!(g.verify_sub_chain(&[]));
+    assert!(g.verify_sub_chain_even(&[]));
+    assert!(g.verify_sub_chain_odd(&[]));
+    assert!(g.verify_sub_chain_strict(&[]));
+}
+
+#[test]
+fn bc_3_reused_nonce_fails_unique_nonce_rule() {
+    let g = Header::genesis();
+    let b1 = g.child(1);
+
+    // Search for an extrinsic that lets a child of b1 reuse b1's nonce and still meet the PoW.
+    let b2 = (0..)
+        .map(|extrinsic| Header {
+            parent: hash(&b1),
+            height: 2,
+            extrinsic,
+            state: b1.state + extrinsic,
+            consensus_digest: b1.consensus_digest,
+            threshold: b1.threshold,
+        })
+        .find(|header| hash(header) < header.threshold)
+        .unwrap();
+    let b3 = b2.child(3);
+
+    assert!(g.verify_sub_chain(&[b1.clone(), b2.clone(), b3.clone()]));
+    assert!(!g.verify_sub_chain_unique_nonces(&[b1.clone(), b2, b3]));
+    assert!(g.verify_sub_chain_unique_nonces(&[b1]));
+}
+
+#[test]
+fn bc_3_mining_with_lenient_threshold() {
+    let lenient_threshold = u64::MAX;
+    let g = Header::genesis_with_threshold(lenient_threshold);
+
+    // Every hash is below the lenient threshold, so each child is found on the first nonce.
+    let mut chain = Vec::new();
+    let mut tip = g.clone();
+    for extrinsic in 1..=50 {
+        tip = tip.child(extrinsic);
+        chain.push(tip.clone());
+    }
+
+    assert_eq!(tip.height, 50);
+    assert_eq!(tip.state, 1275);
+    assert!(g.verify_sub_chain(&chain));
+
+    // A child that claims a different threshold than its parent's is rejected, even though
+    // its hash is below the threshold it claims.
+    let mut b1 = Header::genesis().child(1);
+    b1.threshold = lenient_threshold;
+    assert!(!Header::genesis().verify_sub_chain(&[b1]));
 }
@@ -1,7 +1,7 @@
 //! Untill now, each block has contained just a single extrinsic. Really we would prefer to batch them.
 //! Now, we stop relying solely on headers, and instead, create complete blocks.
 
-use std::{io::Chain, iter};
+use std::{collections::BinaryHeap, io::Chain, iter};
 
 use crate::hash;
 
@@ -9,6 +9,64 @@ use crate::hash;
 // so that the code is slightly more readable.
 type Hash = u64;
 
+/// The most weight the extrinsics of a single block may add up to. Weight models the resources,
+/// like execution time, that it takes to apply an extrinsic.
+pub const MAX_BLOCK_WEIGHT: u64 = 1_000;
+
+/// The weight every extrinsic has before its size is taken into account.
+pub const BASE_EXTRINSIC_WEIGHT: u64 = 1;
+
+/// The weight of a single extrinsic.
+///
+/// The weight is derived from the extrinsic itself, so that nobody can lie about it. Larger
+/// extrinsics cost more: on top of the base weight, each one weighs as much as the number of bits
+/// it takes to write it down.
+pub fn weight_of(extrinsic: u64) -> u64 {
+    BASE_EXTRINSIC_WEIGHT + u64::from(u64::BITS - extrinsic.leading_zeros())
+}
+
+/// Calculate the Merkle root of a batch of extrinsics.
+///
+/// Each extrinsic is hashed into a leaf, and pairs of nodes are hashed together level by level
+/// until a single root is left. When a level has an odd number of nodes, the last one is paired
+/// with itself. Unlike a plain hash of the whole batch, this lets someone prove that a single
+/// extrinsic is in a block without sending the entire body. An empty batch has the default root.
+pub fn merkle_root(extrinsics: &[u64]) -> Hash {
+    let mut level: Vec<Hash> = extrinsics.iter().map(hash).collect();
+    if level.is_empty() {
+        return Hash::default();
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| hash(&(pair[0], *pair.last().unwrap())))
+            .collect();
+    }
+    level[0]
+}
+
+/// How a chain commits to the extrinsics of each block in the header's extrinsics root.
+///
+/// Chains used to commit to a plain hash of the body, and now commit to its Merkle root. A
+/// chain is always verified with a single style, so a chain that mixes the two is rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtrinsicsRootStyle {
+    /// The legacy style: the hash of the whole body.
+    Hash,
+    /// The Merkle root of the body.
+    Merkle,
+}
+
+impl ExtrinsicsRootStyle {
+    /// Calculate the extrinsics root of the given body in this style.
+    pub fn root(&self, extrinsics: &[u64]) -> Hash {
+        match self {
+            ExtrinsicsRootStyle::Hash => hash(&extrinsics),
+            ExtrinsicsRootStyle::Merkle => merkle_root(extrinsics),
+        }
+    }
+}
+
 /// The header no longer contains an extrinsic directly. Rather a vector of extrinsics will be stored in
 /// the block body. We are still storing state in the header for now. This will change in an uncoming
 /// lesson as well.
@@ -22,6 +80,9 @@ pub struct Header {
     extrinsics_root: Hash,
     state: u64,
     pub consensus_digest: u64,
+    // The PoW target this header was mined against. Its hash has to be below it.
+    // `u64::MAX` means the header wasn't mined against any particular difficulty.
+    pub threshold: u64,
 }
 
 // Methods for creating and verifying headers.
@@ -39,22 +100,36 @@ impl Header {
             extrinsics_root: Hash::default(),
             state: 0,
             consensus_digest: 0,
+            threshold: u64::MAX,
         }
     }
 
     /// Create and return a valid child header.
     /// Without the extrinsics themselves, we cannot calculate the final state,
     /// so that information is passed in.
+    ///
+    /// The height saturates at `u64::MAX` instead of overflowing.
     pub fn child(&self, extrinsics_root: Hash, state: u64) -> Self {
         Self {
             parent: hash(self),
-            height: self.height + 1,
+            height: self.height.saturating_add(1),
             extrinsics_root,
             state,
             consensus_digest: 0,
+            threshold: u64::MAX,
         }
     }
 
+    /// The height of this header in the chain.
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// The state after executing all the extrinsics up to and including this header's block.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
     /// Verify a single child header.
     ///
     /// This is a slightly different interface from the previous units. Rather
@@ -62,33 +137,45 @@ impl Header {
     /// This is useful because checking the header can now be thought of as a
     /// subtask of checking an entire block. So it doesn't make sense to check
     /// the entire header chain at once if the chain may be invalid at the second block.
-    fn verify_child(&self, child: &Header) -> bool {
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let genesis = Header::genesis();
+    /// let child = genesis.child(hash(&Vec::<u64>::new()), 0);
+    /// assert!(genesis.verify_child(&child));
+    /// ```
+    pub fn verify_child(&self, child: &Header) -> bool {
         let parent = self ;
-        let mut is_verified = true ;
         if parent.height.saturating_add(1) != child.height {
             return false;
         }
-        is_verified &= hash(parent) == child.parent &&  parent.state == child.state ;
-        is_verified
+        // The state can't be checked here, because that needs the extrinsics from the body.
+        hash(parent) == child.parent
     }
 
     /// Verify that all the headers form a valid chain from this header to the tip.
     ///
     /// We can now trivially write the old verification function in terms of the new one.
-    fn verify_sub_chain(&self, chain: &[Header]) -> bool {
+    pub(crate) fn verify_sub_chain(&self, chain: &[Header]) -> bool {
         let mut prev_header = self ;
-        let mut prev_header_height = self.height ;
-        let mut chain_iter = chain.iter() ;
-        let mut is_verified = true ;
-        while let Some(header) = chain_iter.next() {
-            if prev_header_height.saturating_add(1) != header.height {
+        for header in chain {
+            if !prev_header.verify_child(header) {
                 return false ;
             }
-            is_verified &= hash(prev_header) == header.parent &&  prev_header.state == header.state ;
             prev_header = header ;
-            prev_header_height = header.height ;
         }
-        is_verified
+        true
+    }
+
+    /// Verify the chain like `verify_sub_chain`, but also return the hash of the tip on success,
+    /// so that callers keeping track of reorgs don't need to hash it again. If the chain is
+    /// empty, the tip is this header itself.
+    pub(crate) fn verify_sub_chain_returning_tip(&self, chain: &[Header]) -> Option<Hash> {
+        if !self.verify_sub_chain(chain) {
+            return None ;
+        }
+        Some(hash(chain.last().unwrap_or(self)))
     }
 }
 
@@ -113,6 +200,11 @@ impl Block {
         }
     }
 
+    /// The header of this block.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
     /// Returns the state after executing extrinsics.
     pub fn execute_extrinsics(extrinsics: &Vec<u64>) -> u64 {
         let mut state = 0 ;
@@ -124,19 +216,82 @@ impl Block {
 
     /// Create and return a valid child block.
     /// The extrinsics are batched now, so we need to execute each one of them.
-    pub fn child(&self, extrinsics: Vec<u64>) -> Self {
+    ///
+    /// Extrinsics are included in order until the next one would push the total weight of the
+    /// block over `MAX_BLOCK_WEIGHT`, see `weight_of`. That extrinsic and all the ones after it
+    /// are left out, and can go into a later block.
+    pub fn child(&self, mut extrinsics: Vec<u64>) -> Self {
+        let mut total_weight: u64 = 0;
+        let mut included = 0;
+        for extrinsic in &extrinsics {
+            total_weight += weight_of(*extrinsic);
+            if total_weight > MAX_BLOCK_WEIGHT {
+                break;
+            }
+            included += 1;
+        }
+        extrinsics.truncate(included);
+
         Self {
             header: self.header.child(
-                hash(&extrinsics),
+                merkle_root(&extrinsics),
                 self.header.state + Block::execute_extrinsics(&extrinsics),
             ),
             body: extrinsics,
         }
     }
 
+    /// Check that the extrinsics in the body together fit in a block.
+    fn within_weight_limit(&self) -> bool {
+        self.body.iter().try_fold(0u64, |total, extrinsic| total.checked_add(weight_of(*extrinsic)))
+            .is_some_and(|total| total <= MAX_BLOCK_WEIGHT)
+    }
+
+    /// Create a child block like `child`, but sort the extrinsics in ascending order first.
+    ///
+    /// The resulting state doesn't depend on the order of the extrinsics, but the Merkle root does.
+    /// Sorting gives every batch of extrinsics a single canonical block. A chain that relies on
+    /// this must have its verifiers check the same ordering, and reject any block whose body isn't
+    /// sorted, because `verify_sub_chain` accepts any order.
+    pub fn child_sorted(&self, mut extrinsics: Vec<u64>) -> Self {
+        extrinsics.sort_unstable() ;
+        self.child(extrinsics)
+    }
+
+    /// Build a chain starting from a fresh genesis block, with one child block per batch of
+    /// extrinsics. The returned chain includes the genesis block.
+    pub fn build_chain(extrinsic_batches: &[Vec<u64>]) -> Vec<Block> {
+        let mut chain = vec![Block::genesis()] ;
+        for extrinsics in extrinsic_batches {
+            let child = chain.last().unwrap().child(extrinsics.clone()) ;
+            chain.push(child) ;
+        }
+        chain
+    }
+
     /// Verify that all the given blocks form a valid chain from this block to the tip.
     /// We need to verify the headers as well as execute all transactions and check the final state.
+    /// The extrinsics roots must be Merkle roots.
     pub fn verify_sub_chain(&self, chain: &[Block]) -> bool {
+        self.verify_sub_chain_with_style(ExtrinsicsRootStyle::Merkle, chain)
+    }
+
+    /// Verify the chain like `verify_sub_chain`, but additionally require every block after this
+    /// one to contain at least one extrinsic.
+    ///
+    /// Empty blocks are cheap to produce, so a chain may want to forbid them to stop authors from
+    /// spamming it with blocks that do nothing. On the other hand, a chain that needs blocks to
+    /// keep coming at a steady pace, even when nobody is submitting extrinsics, should stick with
+    /// the permissive `verify_sub_chain`.
+    pub fn verify_sub_chain_nonempty(&self, chain: &[Block]) -> bool {
+        chain.iter().all(|block| !block.body.is_empty()) && self.verify_sub_chain(chain)
+    }
+
+    /// Verify the chain like `verify_sub_chain`, but check the extrinsics roots in the given style.
+    /// Use `ExtrinsicsRootStyle::Hash` to verify chains built before the switch to Merkle roots.
+    ///
+    /// Either way, every block must stay within the weight limit.
+    pub fn verify_sub_chain_with_style(&self, style: ExtrinsicsRootStyle, chain: &[Block]) -> bool {
         let mut prev_block = self ;
         let mut chain_iter = chain.iter() ;
         let mut is_verified = true ;
@@ -144,15 +299,143 @@ impl Block {
             if prev_block.header.height.saturating_add(1) != curr_block.header.height {
                 return false ;
             }
-            // final state in current block = state value of current block + state value of previous block
-            is_verified &= curr_block.header.state == Block::execute_extrinsics(&prev_block.body) + Block::execute_extrinsics(&curr_block.body) &&
-            hash(&curr_block.body) == curr_block.header.extrinsics_root;
+            if !curr_block.within_weight_limit() {
+                return false ;
+            }
+            // final state in current block = state value of previous block + sum of extrinsics in current block
+            is_verified &= curr_block.header.parent == hash(&prev_block.header) &&
+            curr_block.header.state == prev_block.header.state + Block::execute_extrinsics(&curr_block.body) &&
+            style.root(&curr_block.body) == curr_block.header.extrinsics_root;
             prev_block = curr_block ; 
         }
         is_verified
     }
 }
 
+/// Collect the headers of a chain of blocks. Handy for feeding a chain of full blocks into
+/// the header-only fork choice rules.
+pub fn headers_of(blocks: &[Block]) -> Vec<Header> {
+    blocks.iter().map(|block| block.header().clone()).collect()
+}
+
+/// Verify headers that may have arrived in any order, such as from several peers at once.
+/// They are sorted by height before running the normal verification from the given genesis, so
+/// callers don't need to sort them first. Two headers at the same height can never both be
+/// part of one chain, so they make the chain invalid.
+pub fn verify_unordered(genesis: &Header, mut chain: Vec<Header>) -> bool {
+    chain.sort_by_key(|header| header.height);
+    if chain.windows(2).any(|pair| pair[0].height == pair[1].height) {
+        return false;
+    }
+    genesis.verify_sub_chain(&chain)
+}
+
+/// The reasons `from_text` can fail to read a chain back. Lines are numbered from one.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ParseError {
+    /// The line doesn't have five or six fields.
+    WrongFieldCount { line: usize },
+    /// A field on the line isn't a valid `u64`.
+    InvalidNumber { line: usize },
+}
+
+/// Write the headers as plain text, one line per header. Each line holds the height, the parent
+/// hash, the extrinsics root, the state, the consensus digest and the threshold, separated by
+/// spaces. This is a lightweight way to save a chain or pass it around without any
+/// serialization library.
+pub fn to_text(chain: &[Header]) -> String {
+    chain
+        .iter()
+        .map(|header| {
+            format!(
+                "{} {} {} {} {} {}\n",
+                header.height,
+                header.parent,
+                header.extrinsics_root,
+                header.state,
+                header.consensus_digest,
+                header.threshold
+            )
+        })
+        .collect()
+}
+
+/// Read back headers written by `to_text`. Blank lines are skipped. The threshold may be left
+/// out, in which case the header wasn't mined against any particular difficulty.
+pub fn from_text(text: &str) -> Result<Vec<Header>, ParseError> {
+    let mut chain = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = line
+            .split_whitespace()
+            .map(|field| field.parse::<u64>())
+            .collect::<Result<Vec<u64>, _>>()
+            .map_err(|_| ParseError::InvalidNumber { line: line_number })?;
+        let (fields, threshold) = match fields.as_slice() {
+            [rest @ .., threshold] if rest.len() == 5 => (rest, *threshold),
+            rest if rest.len() == 5 => (rest, u64::MAX),
+            _ => return Err(ParseError::WrongFieldCount { line: line_number }),
+        };
+        chain.push(Header {
+            height: fields[0],
+            parent: fields[1],
+            extrinsics_root: fields[2],
+            state: fields[3],
+            consensus_digest: fields[4],
+            threshold,
+        });
+    }
+    Ok(chain)
+}
+
+/// A pool of pending extrinsics waiting to be included in a block.
+///
+/// Block authors don't include extrinsics in the order they arrive. Rather, they pick the most
+/// valuable ones first. Here the priority of an extrinsic is simply its value, so higher
+/// extrinsics are drained first.
+#[derive(Debug, Default, Clone)]
+pub struct Mempool {
+    pending: BinaryHeap<u64>,
+}
+
+impl Mempool {
+    /// Returns a new, empty mempool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an extrinsic to the pool of pending extrinsics.
+    pub fn submit(&mut self, extrinsic: u64) {
+        self.pending.push(extrinsic) ;
+    }
+
+    /// Number of extrinsics still pending.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether there are no pending extrinsics.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Remove and return up to `max` extrinsics, highest priority first, ready to be
+    /// passed to `Block::child`. Extrinsics that don't fit stay in the pool.
+    pub fn drain_for_block(&mut self, max: usize) -> Vec<u64> {
+        let mut extrinsics = Vec::new() ;
+        while extrinsics.len() < max {
+            match self.pending.pop() {
+                Some(extrinsic) => extrinsics.push(extrinsic),
+                None => break,
+            }
+        }
+        extrinsics
+    }
+}
+
 /// Create an invalid child block of the given block. Although the child block is invalid,
 /// the header should be valid.
 ///
@@ -259,6 +542,7 @@ fn bc_4_invalid_header_does_not_check() {
         extrinsics_root: 0,
         state: 100,
         consensus_digest: 0,
+        threshold: u64::MAX,
     };
 
     assert!(!g.verify_child(&h1));
@@ -295,4 +579,259 @@ fn bc_4_student_invalid_block_really_is_invalid() {
 
     // Make sure that the block is not valid when executed.
     assert!(!gb.verify_sub_chain(&[b1]));
-}
\ No newline at end of file
+}
+
+#[test]
+fn bc_4_verify_child_directly() {
+    let g = Header::genesis();
+    let h1 = g.child(hash(&Vec::<u64>::new()), 0);
+
+    assert!(g.verify_child(&h1));
+    assert!(!h1.verify_child(&g));
+}
+
+#[test]
+fn bc_4_build_chain() {
+    let chain = Block::build_chain(&[vec![1, 2], vec![3], vec![4, 5, 6]]);
+
+    assert_eq!(chain.len(), 4);
+    assert_eq!(chain[0], Block::genesis());
+    assert_eq!(chain[3].header.height, 3);
+    assert_eq!(chain[3].header.state, 21);
+    assert!(chain[0].verify_sub_chain(&chain[1..]));
+}
+
+#[test]
+fn bc_4_mempool_drains_by_priority() {
+    let mut pool = Mempool::new();
+    for extrinsic in [3, 10, 1, 7] {
+        pool.submit(extrinsic);
+    }
+
+    assert_eq!(pool.drain_for_block(10), vec![10, 7, 3, 1]);
+    assert!(pool.is_empty());
+}
+
+#[test]
+fn bc_4_mempool_respects_max() {
+    let mut pool = Mempool::new();
+    for extrinsic in [3, 10, 1, 7] {
+        pool.submit(extrinsic);
+    }
+
+    let g = Block::genesis();
+    let b1 = g.child(pool.drain_for_block(2));
+    assert_eq!(b1.body, vec![10, 7]);
+    assert_eq!(pool.len(), 2);
+
+    let b2 = b1.child(pool.drain_for_block(2));
+    assert_eq!(b2.body, vec![3, 1]);
+    assert!(pool.drain_for_block(2).is_empty());
+    assert!(g.verify_sub_chain(&[b1, b2]));
+}
+
+#[test]
+fn bc_4_merkle_root_depends_on_order() {
+    assert_eq!(merkle_root(&[]), 0);
+    assert_eq!(merkle_root(&[7]), hash(&7u64));
+    assert_eq!(merkle_root(&[1, 2, 3]), merkle_root(&[1, 2, 3]));
+    assert_ne!(merkle_root(&[1, 2, 3]), merkle_root(&[3, 2, 1]));
+}
+
+#[test]
+fn bc_4_reordered_body_doesnt_check() {
+    let g = Block::genesis();
+    let mut b1 = g.child(vec![1, 2, 3]);
+    assert!(g.verify_sub_chain(&[b1.clone()]));
+
+    // Same extrinsics and the same resulting state, but a different Merkle root.
+    b1.body = vec![3, 2, 1];
+    assert!(!g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_4_legacy_hash_roots_only_verify_in_legacy_style() {
+    let legacy_child = |parent: &Block, extrinsics: Vec<u64>| Block {
+        header: parent.header.child(
+            hash(&extrinsics),
+            parent.header.state + Block::execute_extrinsics(&extrinsics),
+        ),
+        body: extrinsics,
+    };
+
+    let g = Block::genesis();
+    let b1 = legacy_child(&g, vec![1, 2]);
+    let b2 = legacy_child(&b1, vec![3]);
+    assert!(g.verify_sub_chain_with_style(ExtrinsicsRootStyle::Hash, &[b1.clone(), b2]));
+    assert!(!g.verify_sub_chain(std::slice::from_ref(&b1)));
+
+    // A chain mixing both styles doesn't verify in either of them.
+    let b2 = b1.child(vec![3]);
+    assert!(!g.verify_sub_chain_with_style(ExtrinsicsRootStyle::Hash, &[b1.clone(), b2.clone()]));
+    assert!(!g.verify_sub_chain_with_style(ExtrinsicsRootStyle::Merkle, &[b1, b2]));
+}
+
+#[test]
+fn bc_4_child_height_saturates() {
+    let mut b = Block::genesis();
+    b.header.height = u64::MAX;
+
+    let child = b.child(vec![1, 2]);
+    assert_eq!(child.header.height, u64::MAX);
+}
+
+#[test]
+fn bc_4_headers_of_chain() {
+    let chain = Block::build_chain(&[vec![1, 2], vec![3], vec![]]);
+    let headers = headers_of(&chain);
+
+    assert_eq!(headers.len(), chain.len());
+    for (header, block) in headers.iter().zip(chain.iter()) {
+        assert_eq!(header, block.header());
+    }
+    for window in headers.windows(2) {
+        assert_eq!(window[1].parent, hash(&window[0]));
+    }
+    assert_eq!(headers_of(&[]), Vec::new());
+}
+
+#[test]
+fn bc_4_header_chain_of_built_blocks_verifies() {
+    let headers = headers_of(&Block::build_chain(&[vec![1, 2], vec![3]]));
+    assert!(headers[0].verify_sub_chain(&headers[1..]));
+}
+
+#[test]
+fn bc_4_empty_block_fails_only_strict_check() {
+    let chain = Block::build_chain(&[vec![1], vec![], vec![2]]);
+
+    assert!(chain[0].verify_sub_chain(&chain[1..]));
+    assert!(!chain[0].verify_sub_chain_nonempty(&chain[1..]));
+
+    // The genesis block is empty by convention, and that is fine.
+    assert!(chain[0].verify_sub_chain_nonempty(&chain[1..2]));
+}
+
+#[test]
+fn bc_4_verify_returns_tip_hash() {
+    let headers = headers_of(&Block::build_chain(&[vec![1, 2], vec![3]]));
+
+    assert_eq!(headers[0].verify_sub_chain_returning_tip(&headers[1..]), Some(hash(headers.last().unwrap())));
+    assert_eq!(headers[0].verify_sub_chain_returning_tip(&[]), Some(hash(&headers[0])));
+}
+
+#[test]
+fn bc_4_verify_returning_tip_rejects_bad_chain() {
+    let mut headers = headers_of(&Block::build_chain(&[vec![1, 2], vec![3]]));
+    headers[2].parent = 0;
+
+    assert_eq!(headers[0].verify_sub_chain_returning_tip(&headers[1..]), None);
+}
+
+#[test]
+fn bc_4_child_sorted_is_canonical() {
+    let g = Block::genesis();
+    let b1 = g.child_sorted(vec![3, 1, 2]);
+    let b2 = g.child_sorted(vec![2, 3, 1]);
+
+    assert_eq!(b1, b2);
+    assert_eq!(b1.body, vec![1, 2, 3]);
+    assert_ne!(g.child(vec![3, 1, 2]), g.child(vec![2, 3, 1]));
+    assert!(g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_4_weight_of_grows_with_extrinsic_size() {
+    assert_eq!(weight_of(0), BASE_EXTRINSIC_WEIGHT);
+    assert_eq!(weight_of(1), BASE_EXTRINSIC_WEIGHT + 1);
+    assert_eq!(weight_of(255), BASE_EXTRINSIC_WEIGHT + 8);
+    assert_eq!(weight_of(u64::MAX), BASE_EXTRINSIC_WEIGHT + 64);
+}
+
+#[test]
+fn bc_4_child_truncates_batch_at_weight_limit() {
+    let g = Block::genesis();
+    // Each extrinsic weighs 2, so only the first 500 fit.
+    let b1 = g.child(vec![1; 1_000_000]);
+
+    assert_eq!(b1.body, vec![1; 500]);
+    assert_eq!(b1.header.state, 500);
+    assert!(g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_4_child_fills_block_exactly_to_weight_limit() {
+    let g = Block::genesis();
+    // 332 extrinsics of weight 3 and 2 of weight 2 add up to exactly 1000.
+    let mut extrinsics = vec![3; 332];
+    extrinsics.extend([1, 1]);
+    let b1 = g.child(extrinsics.clone());
+
+    assert_eq!(b1.body, extrinsics);
+    assert!(g.verify_sub_chain(std::slice::from_ref(&b1)));
+
+    // One more extrinsic of any size would not fit.
+    assert_eq!(g.child([extrinsics, vec![0]].concat()), b1);
+}
+
+#[test]
+fn bc_4_overweight_block_fails_verification() {
+    let g = Block::genesis();
+    // Build a block that commits to one extrinsic more than the weight limit allows.
+    let extrinsics = vec![1; 501];
+    let b1 = Block {
+        header: g.header.child(merkle_root(&extrinsics), Block::execute_extrinsics(&extrinsics)),
+        body: extrinsics,
+    };
+
+    assert!(!g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_4_empty_chain_is_valid() {
+    let g = Block::genesis();
+
+    assert!(g.header.verify_sub_chain(&[]));
+    assert!(g.verify_sub_chain(&[]));
+    assert!(g.verify_sub_chain_nonempty(&[]));
+    assert!(g.verify_sub_chain_with_style(ExtrinsicsRootStyle::Hash, &[]));
+
+    // The same holds when starting from a block other than genesis.
+    let b1 = g.child(vec![1, 2]);
+    assert!(b1.header.verify_sub_chain(&[]));
+    assert!(b1.verify_sub_chain(&[]));
+}
+
+#[test]
+fn bc_4_text_round_trip() {
+    let mut chain = headers_of(&Block::build_chain(&[vec![1, 2], vec![3]]));
+    chain[2].consensus_digest = 42;
+    chain[2].threshold = u64::MAX / 100;
+    assert_eq!(chain.len(), 3);
+
+    let text = to_text(&chain);
+    assert_eq!(text.lines().count(), 3);
+    assert_eq!(from_text(&text), Ok(chain));
+}
+
+#[test]
+fn bc_4_from_text_rejects_malformed_lines() {
+    assert_eq!(from_text("0 0 0 0 0\n1 2 3\n"), Err(ParseError::WrongFieldCount { line: 2 }));
+    assert_eq!(from_text("0 0 zero 0 0\n"), Err(ParseError::InvalidNumber { line: 1 }));
+    assert_eq!(from_text(""), Ok(vec![]));
+}
+
+#[test]
+fn bc_4_verify_unordered_sorts_shuffled_chain() {
+    let headers = headers_of(&Block::build_chain(&[vec![1], vec![2, 3], vec![4], vec![5, 6]]));
+    let genesis = &headers[0];
+
+    let shuffled = vec![headers[3].clone(), headers[1].clone(), headers[4].clone(), headers[2].clone()];
+    assert!(!genesis.verify_sub_chain(&shuffled));
+    assert!(verify_unordered(genesis, shuffled));
+
+    // A second header at an existing height is rejected, even one that would link up.
+    let sibling = headers[1].child(merkle_root(&[7]), 8);
+    let with_duplicate = vec![headers[2].clone(), sibling, headers[1].clone()];
+    assert!(!verify_unordered(genesis, with_duplicate));
+}
@@ -7,6 +7,8 @@
 //! Since we have nothing to add to the Block or Header data structures in this lesson,
 //! we will import them from the previous lesson.
 
+use std::cmp::Ordering ;
+use std::marker::PhantomData;
 use std::u64;
 
 use super::p4_batched_extrinsics::{Block, Header} ;
@@ -15,6 +17,8 @@ use rand::Rng ;
 
 const THRESHOLD: u64 = u64::max_value() / 100 ;
 
+type Hash = u64 ;
+
 /// Judge which blockchain is "best" when there are multiple candidates. There are several
 /// meaningful notions of "best" which is why this is a trait instead of just a
 /// method.
@@ -36,6 +40,45 @@ pub trait ForkChoice {
     /// two chains. Therefore this method has a provided implementation. However,
     /// it may be much more performant to write a fork-choice-specific implementation.
     fn best_chain<'a>(candidate_chains: &[&'a [Header]]) -> &'a [Header] ;
+
+    /// Compare many chains and return the best one among those that are actually valid.
+    ///
+    /// Unlike the other methods, this one doesn't assume the chains are valid. Each chain is
+    /// checked from its first header to its tip, and invalid or empty chains are left out
+    /// before picking the best. Returns `None` if none of the chains are valid.
+    fn best_valid_chain<'a>(candidate_chains: &[&'a [Header]]) -> Option<&'a [Header]> {
+        let valid_chains: Vec<&'a [Header]> = candidate_chains
+            .iter()
+            .copied()
+            .filter(|chain| match chain.split_first() {
+                Some((first, rest)) => first.verify_sub_chain(rest),
+                None => false,
+            })
+            .collect() ;
+
+        if valid_chains.is_empty() {
+            return None ;
+        }
+        Some(Self::best_chain(&valid_chains))
+    }
+
+    /// Sort all the candidate chains from best to worst, which shows the whole field of
+    /// competing forks rather than just the winner.
+    ///
+    /// Like `best_chain`, this only needs pairwise comparisons. Chains that the rule considers
+    /// equally good keep their original order. As with the other methods, the chains are
+    /// assumed to be valid.
+    fn rank_chains<'a>(candidate_chains: &[&'a [Header]]) -> Vec<&'a [Header]> {
+        let mut ranked = candidate_chains.to_vec() ;
+        ranked.sort_by(|chain_1, chain_2| {
+            match (Self::first_chain_is_better(chain_1, chain_2), Self::first_chain_is_better(chain_2, chain_1)) {
+                (true, false) => Ordering::Less,
+                (false, true) => Ordering::Greater,
+                _ => Ordering::Equal,
+            }
+        }) ;
+        ranked
+    }
 }
 
 /// The "best" chain is simply the longest chain.
@@ -71,7 +114,18 @@ impl ForkChoice for LongestChainRule {
 /// nonces. Modeling the amount of work required to achieve a particular hash
 /// is out of scope for this exercise, so we will use the not-really-right-but
 /// conceptually-good-enough formula `work = THRESHOLD - block_hash`.
-pub struct HeaviestChainRule ;
+///
+/// This measures work against the module's `THRESHOLD`. For chains mined at another
+/// difficulty, use `ThresholdHeaviestRule` with their threshold.
+pub type HeaviestChainRule = ThresholdHeaviestRule<THRESHOLD> ;
+
+/// The heaviest chain rule for chains mined against the hash threshold `T`.
+///
+/// The threshold is a const parameter rather than a field, so that every `ForkChoice` method
+/// measures work against it. For example `ThresholdHeaviestRule::<{ u64::MAX / 2 }>` compares
+/// chains mined at half the hash space.
+#[derive(Default)]
+pub struct ThresholdHeaviestRule<const T: u64> ;
 
 /// Generating a random nonce.
 fn generate_nonce() -> u64 {
@@ -80,7 +134,9 @@ fn generate_nonce() -> u64 {
 }
 
 /// Creating a valid header according to Proof of Work.
+/// The threshold is recorded in the header so that its difficulty is known later on.
 fn mine_consensus_digest(header: &mut Header, threshold: u64) {
+    header.threshold = threshold ;
     let mut valid_header = header.clone() ;
     loop {
         let nonce = generate_nonce() ;
@@ -96,25 +152,42 @@ fn mine_consensus_digest(header: &mut Header, threshold: u64) {
 /// This will be useful for exploring the heaviest chain rule. The expected
 /// usage is that you create a block using the normal `Block.child()` method
 /// and then pass the block to this helper for additional mining.
-fn mine_extra_hard(block: &mut Block, threshold: u64) {
+pub fn mine_extra_hard(block: &mut Block, threshold: u64) {
     mine_consensus_digest(&mut block.header, threshold)
 }
 
-impl HeaviestChainRule {
+/// Search for a consensus digest that makes the header's hash even, or odd if `even` is false.
+/// Useful for building chains to exercise the `MostBlocksWithEvenHash` rule.
+fn mine_for_parity(header: &mut Header, even: bool) {
+    let wanted_remainder = if even { 0 } else { 1 } ;
+    header.consensus_digest = 0 ;
+    while hash(header) % 2 != wanted_remainder {
+        header.consensus_digest += 1 ;
+    }
+}
+
+impl<const T: u64> ThresholdHeaviestRule<T> {
     /// Work done on individual chains.
-    fn get_work(chain: &[Header]) -> i64 {
+    /// Calculated as `i128` so that hashes above `i64::MAX` don't wrap around into huge amounts of work.
+    fn get_work(chain: &[Header]) -> i128 {
         let mut work = 0 ;
         chain.iter().for_each(|header| {
-            work = (work as i64).saturating_add(THRESHOLD as i64 - hash(header) as i64) ;
+            work += Self::work_of_hash(hash(header)) ;
         }) ;
         work
     }
+
+    /// The work contained in a single block with the given hash. Only the hash matters, so this
+    /// works for any kind of block, not just the headers in this lesson.
+    pub(crate) fn work_of_hash(block_hash: u64) -> i128 {
+        T as i128 - block_hash as i128
+    }
 }
 
-impl ForkChoice for HeaviestChainRule {
+impl<const T: u64> ForkChoice for ThresholdHeaviestRule<T> {
     fn first_chain_is_better(chain_1: &[Header], chain_2: &[Header]) -> bool {
         let mut is_better = true ;
-        if HeaviestChainRule::get_work(chain_1) < HeaviestChainRule::get_work(chain_2) {
+        if Self::get_work(chain_1) < Self::get_work(chain_2) {
             is_better &= false ;
         }
         is_better
@@ -125,7 +198,7 @@ impl ForkChoice for HeaviestChainRule {
         let mut best_chain = chain_iter.next().unwrap() ;
 
         while let Some(next_chain) = chain_iter.next() {
-            if HeaviestChainRule::get_work(next_chain) > HeaviestChainRule::get_work(best_chain) {
+            if Self::get_work(next_chain) > Self::get_work(best_chain) {
                 best_chain = next_chain ;
             } 
         }
@@ -133,6 +206,45 @@ impl ForkChoice for HeaviestChainRule {
     }
 }
 
+/// The "best" chain is the one with the most accumulated work, where every block is weighted
+/// by the difficulty it was actually mined at.
+///
+/// `HeaviestChainRule` measures work against a single threshold, which only makes sense when
+/// every block is mined at the same difficulty. Here every header records its own threshold,
+/// and we use the same `threshold - block_hash` formula against it. A block whose hash doesn't
+/// get below its threshold counts for no work at all.
+pub struct VariableDifficultyHeaviestRule ;
+
+impl VariableDifficultyHeaviestRule {
+    /// Work done on the block with the given header, measured against its own threshold.
+    fn work_of(header: &Header) -> u128 {
+        header.threshold.saturating_sub(hash(header)) as u128
+    }
+
+    /// Work done on all the blocks in the chain.
+    fn get_work(chain: &[Header]) -> u128 {
+        chain.iter().map(VariableDifficultyHeaviestRule::work_of).sum()
+    }
+}
+
+impl ForkChoice for VariableDifficultyHeaviestRule {
+    fn first_chain_is_better(chain_1: &[Header], chain_2: &[Header]) -> bool {
+        VariableDifficultyHeaviestRule::get_work(chain_1) >= VariableDifficultyHeaviestRule::get_work(chain_2)
+    }
+
+    fn best_chain<'a>(candidate_chains: &[&'a [Header]]) -> &'a [Header] {
+        let mut chain_iter = candidate_chains.iter() ;
+        let mut best_chain = chain_iter.next().unwrap() ;
+
+        for next_chain in chain_iter {
+            if VariableDifficultyHeaviestRule::get_work(next_chain) > VariableDifficultyHeaviestRule::get_work(best_chain) {
+                best_chain = next_chain ;
+            }
+        }
+        best_chain
+    }
+}
+
 /// The best chain is the one with the most blocks that have even hashes.
 ///
 /// This exact rule is a bit contrived, but it does model a family of fork choice rules
@@ -147,6 +259,9 @@ impl ForkChoice for HeaviestChainRule {
 /// 2. Interleaved Pow/PoA. In each round there is one author who is allowed to author.
 ///    Anyone else is allowed to mine a PoW-style block. The best chain is the one with
 ///    the most PoA blocks, and ties are broken by the most accumulated work.
+///
+/// The first example is implemented for real as `MostPrimaryAuthoredRule` in the
+/// Proof of Authority lesson.
 pub struct MostBlocksWithEvenHash ;
 
 impl MostBlocksWithEvenHash {
@@ -184,6 +299,224 @@ impl ForkChoice for MostBlocksWithEvenHash {
     }
 }
 
+/// The best chain is the one whose header hashes add up to the smallest total.
+///
+/// This is a toy stand-in for lowest-difficulty-target tie breaking. Unlike the heaviest
+/// chain rule, it doesn't reward length, so adding blocks to a chain only ever makes it worse.
+pub struct SmallestHashSumRule ;
+
+impl SmallestHashSumRule {
+    /// Sum of all header hashes in the chain. Summed as `u128` so it can't overflow.
+    fn hash_sum(chain: &[Header]) -> u128 {
+        chain.iter().map(|header| hash(header) as u128).sum()
+    }
+}
+
+impl ForkChoice for SmallestHashSumRule {
+    fn first_chain_is_better(chain_1: &[Header], chain_2: &[Header]) -> bool {
+        SmallestHashSumRule::hash_sum(chain_1) <= SmallestHashSumRule::hash_sum(chain_2)
+    }
+
+    fn best_chain<'a>(candidate_chains: &[&'a [Header]]) -> &'a [Header] {
+        let mut chain_iter = candidate_chains.iter() ;
+        let mut best_chain = chain_iter.next().unwrap() ;
+
+        for next_chain in chain_iter {
+            if SmallestHashSumRule::hash_sum(next_chain) < SmallestHashSumRule::hash_sum(best_chain) {
+                best_chain = next_chain ;
+            }
+        }
+        best_chain
+    }
+}
+
+/// Layers two fork choice rules. The primary rule `A` decides which chain is better, and only
+/// when `A` considers both chains equally good does the secondary rule `B` break the tie.
+///
+/// Real systems commonly layer their rules this way. For example, the longest chain wins, and
+/// among chains of equal length, the one with the most accumulated work wins.
+pub struct CompositeForkChoice<A: ForkChoice, B: ForkChoice>(PhantomData<(A, B)>) ;
+
+impl<A: ForkChoice, B: ForkChoice> ForkChoice for CompositeForkChoice<A, B> {
+    fn first_chain_is_better(chain_1: &[Header], chain_2: &[Header]) -> bool {
+        let first_at_least_as_good = A::first_chain_is_better(chain_1, chain_2) ;
+        let second_at_least_as_good = A::first_chain_is_better(chain_2, chain_1) ;
+
+        // The primary rule sees the two chains as equal, so defer to the secondary rule.
+        if first_at_least_as_good && second_at_least_as_good {
+            return B::first_chain_is_better(chain_1, chain_2) ;
+        }
+        first_at_least_as_good
+    }
+
+    fn best_chain<'a>(candidate_chains: &[&'a [Header]]) -> &'a [Header] {
+        let mut chain_iter = candidate_chains.iter() ;
+        let mut best_chain = chain_iter.next().unwrap() ;
+
+        for next_chain in chain_iter {
+            if !Self::first_chain_is_better(best_chain, next_chain) {
+                best_chain = next_chain ;
+            }
+        }
+        best_chain
+    }
+}
+
+/// Like `ForkChoice`, but for rules that need to look inside the blocks rather than only at
+/// the headers. The same assumptions about the chains apply.
+pub trait BlockForkChoice {
+    /// Compare two chains of full blocks, and return the "best" one.
+    fn first_chain_is_better(chain_1: &[Block], chain_2: &[Block]) -> bool ;
+
+    /// Compare many chains of full blocks and return the best one.
+    fn best_chain<'a>(candidate_chains: &[&'a [Block]]) -> &'a [Block] ;
+}
+
+/// The best chain is the one whose blocks carry the most extrinsics in total.
+///
+/// This favours throughput. Since the header only commits to the extrinsics through the
+/// extrinsics root, we need the full blocks to count them.
+pub struct MostExtrinsicsRule ;
+
+impl MostExtrinsicsRule {
+    /// Total number of extrinsics in all the blocks of the chain.
+    fn count_extrinsics(chain: &[Block]) -> usize {
+        chain.iter().map(|block| block.body.len()).sum()
+    }
+}
+
+impl BlockForkChoice for MostExtrinsicsRule {
+    fn first_chain_is_better(chain_1: &[Block], chain_2: &[Block]) -> bool {
+        MostExtrinsicsRule::count_extrinsics(chain_1) >= MostExtrinsicsRule::count_extrinsics(chain_2)
+    }
+
+    fn best_chain<'a>(candidate_chains: &[&'a [Block]]) -> &'a [Block] {
+        let mut chain_iter = candidate_chains.iter() ;
+        let mut best_chain = chain_iter.next().unwrap() ;
+
+        for next_chain in chain_iter {
+            if MostExtrinsicsRule::count_extrinsics(next_chain) > MostExtrinsicsRule::count_extrinsics(best_chain) {
+                best_chain = next_chain ;
+            }
+        }
+        best_chain
+    }
+}
+
+/// Everything the fork choice rules look at in a chain, gathered in a single pass so that it can
+/// be shown all at once.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ChainSummary {
+    /// The number of headers in the chain.
+    pub length: usize,
+    /// The work in the chain as `VariableDifficultyHeaviestRule` measures it.
+    pub total_work: u128,
+    /// The hash of the last header, or the default hash for an empty chain.
+    pub tip_hash: Hash,
+    /// The number of headers whose hash is even, as counted by `MostBlocksWithEvenHash`.
+    pub even_blocks: usize,
+}
+
+/// Summarize the chain for display.
+pub fn summarize(chain: &[Header]) -> ChainSummary {
+    let mut summary = ChainSummary {
+        length: chain.len(),
+        total_work: 0,
+        tip_hash: Hash::default(),
+        even_blocks: 0,
+    } ;
+    for header in chain {
+        let header_hash = hash(header) ;
+        summary.total_work += VariableDifficultyHeaviestRule::work_of(header) ;
+        if header_hash % 2 == 0 {
+            summary.even_blocks += 1 ;
+        }
+        summary.tip_hash = header_hash ;
+    }
+    summary
+}
+
+/// Find the fork point of two chains. Returns the index of the deepest header the two
+/// chains share, walking forward from their first headers, or `None` if they don't
+/// even share their first header.
+///
+/// Both chains are assumed to be indexed the same way, e.g. both start at genesis.
+pub fn last_common_ancestor(a: &[Header], b: &[Header]) -> Option<usize> {
+    a.iter()
+        .zip(b.iter())
+        .take_while(|(header_a, header_b)| hash(header_a) == hash(header_b))
+        .count()
+        .checked_sub(1)
+}
+
+/// Whether the candidate chain starts with exactly the headers of the base chain and adds at
+/// least one block on top. Switching from the base to such a candidate doesn't retract any
+/// blocks, so a reorg can skip looking for the fork point.
+pub fn is_extension_of(base: &[Header], candidate: &[Header]) -> bool {
+    candidate.len() > base.len() && candidate.starts_with(base)
+}
+
+/// Count how many blocks sit on top of the target header in the chain. The tip has zero
+/// confirmations. Returns `None` if the target isn't in the chain at all.
+pub fn confirmations(chain: &[Header], target: &Header) -> Option<usize> {
+    let index = chain.iter().position(|header| header == target)? ;
+    Some(chain.len() - 1 - index)
+}
+
+/// Render several chains sharing a common prefix as an ASCII fork diagram, in the same style
+/// as the diagrams throughout these lessons. The shared prefix is drawn once, followed by the
+/// rest of the first chain. Every other chain branches off below at the divergence point, and
+/// its blocks are marked with one prime per branch to tell them apart.
+///
+/// ```text
+/// G -- 1 -- 2 -- 3 -- 4
+///            \-- 3' -- 4'
+/// ```
+///
+/// Blocks are labelled by height, with `G` for the genesis block.
+pub fn render_forks(chains: &[&[Header]]) -> String {
+    let label = |header: &Header, primes: usize| -> String {
+        if header.height() == 0 {
+            String::from("G")
+        } else {
+            format!("{}{}", header.height(), "'".repeat(primes))
+        }
+    } ;
+
+    let first_chain = match chains.first() {
+        Some(chain) => *chain,
+        None => return String::new(),
+    } ;
+    let prefix_len = chains.iter()
+        .map(|chain| last_common_ancestor(first_chain, chain).map_or(0, |index| index + 1))
+        .min()
+        .unwrap_or(0) ;
+
+    let prefix = first_chain[..prefix_len].iter()
+        .map(|header| label(header, 0))
+        .collect::<Vec<_>>()
+        .join(" -- ") ;
+
+    let mut lines = Vec::new() ;
+    for (index, chain) in chains.iter().enumerate() {
+        let suffix = chain[prefix_len..].iter()
+            .map(|header| label(header, index))
+            .collect::<Vec<_>>()
+            .join(" -- ") ;
+
+        if index == 0 {
+            match (prefix.is_empty(), suffix.is_empty()) {
+                (_, true) => lines.push(prefix.clone()),
+                (true, false) => lines.push(suffix),
+                (false, false) => lines.push(format!("{} -- {}", prefix, suffix)),
+            }
+        } else if !suffix.is_empty() {
+            lines.push(format!("{}\\-- {}", " ".repeat(prefix.len()), suffix)) ;
+        }
+    }
+    lines.join("\n")
+}
+
 /// Build and return two different chains with a common prefix.
 /// They should have the same genesis header. Both chains should be valid.
 /// The first chain should be longer (have more blocks), but the second
@@ -284,40 +617,40 @@ fn bc_5_heaviest_chain() {
     assert_eq!(HeaviestChainRule::best_chain(&[chain_1, chain_2]), chain_2);
 }
 
+#[test]
+fn bc_5_heaviest_chain_counts_high_hashes_as_little_work() {
+    let g = Header::genesis();
+
+    // Find a block whose hash doesn't fit in an `i64`. It is far above the threshold, so it
+    // contains less work than no block at all.
+    let mut i = 0;
+    let high = loop {
+        let header = g.child(hash(&[i]), i);
+        if hash(&header) > i64::MAX as u64 {
+            break header;
+        }
+        i += 1;
+    };
+    let chain = [high];
+
+    assert!(HeaviestChainRule::first_chain_is_better(&[], &chain));
+    assert!(!HeaviestChainRule::first_chain_is_better(&chain, &[]));
+}
+
 #[test]
 fn bc_5_most_even_blocks() {
     let g = Header::genesis();
 
     let mut h_a1 = g.child(2, 0);
-    for i in 0..u64::max_value() {
-        h_a1 = g.child(2, i);
-        if hash(&h_a1) % 2 == 0 {
-            break;
-        }
-    }
-    let mut h_a2 = g.child(2, 0);
-    for i in 0..u64::max_value() {
-        h_a2 = h_a1.child(2, i);
-        if hash(&h_a2) % 2 == 0 {
-            break;
-        }
-    }
+    mine_for_parity(&mut h_a1, true);
+    let mut h_a2 = h_a1.child(2, 0);
+    mine_for_parity(&mut h_a2, true);
     let chain_1 = &[g.clone(), h_a1, h_a2];
 
     let mut h_b1 = g.child(2, 0);
-    for i in 0..u64::max_value() {
-        h_b1 = g.child(2, i);
-        if hash(&h_b1) % 2 != 0 {
-            break;
-        }
-    }
-    let mut h_b2 = g.child(2, 0);
-    for i in 0..u64::max_value() {
-        h_b2 = h_b1.child(2, i);
-        if hash(&h_b2) % 2 != 0 {
-            break;
-        }
-    }
+    mine_for_parity(&mut h_b1, false);
+    let mut h_b2 = h_b1.child(2, 0);
+    mine_for_parity(&mut h_b2, false);
     let chain_2 = &[g, h_b1, h_b2];
 
     assert!(MostBlocksWithEvenHash::first_chain_is_better(
@@ -355,4 +688,320 @@ fn bc_5_longest_vs_heaviest() {
         HeaviestChainRule::best_chain(&[&longest_chain, &pow_chain]),
         &pow_chain
     );
-}
\ No newline at end of file
+}
+
+#[test]
+fn bc_5_composite_breaks_length_tie_by_work() {
+    let g = Header::genesis() ;
+
+    let mut i = 0 ;
+    let h_a1 = loop {
+        let header = g.child(hash(&[i]), i) ;
+        // Less work than the other chain.
+        if hash(&header) > THRESHOLD {
+            break header ;
+        }
+        i += 1 ;
+    } ;
+    let light_chain = &[g.clone(), h_a1] ;
+
+    let h_b1 = loop {
+        let header = g.child(hash(&[i]), i) ;
+        // More work than the other chain.
+        if hash(&header) < THRESHOLD {
+            break header ;
+        }
+        i += 1 ;
+    } ;
+    let heavy_chain = &[g, h_b1] ;
+
+    type LongestThenHeaviest = CompositeForkChoice<LongestChainRule, HeaviestChainRule> ;
+
+    // Both chains have the same length, so the longest chain rule alone can't tell them apart.
+    assert!(LongestChainRule::first_chain_is_better(light_chain, heavy_chain)) ;
+    assert!(LongestChainRule::first_chain_is_better(heavy_chain, light_chain)) ;
+
+    assert!(LongestThenHeaviest::first_chain_is_better(heavy_chain, light_chain)) ;
+    assert!(!LongestThenHeaviest::first_chain_is_better(light_chain, heavy_chain)) ;
+    assert_eq!(LongestThenHeaviest::best_chain(&[light_chain, heavy_chain]), heavy_chain) ;
+}
+
+#[test]
+fn bc_5_smallest_hash_sum() {
+    let g = Header::genesis() ;
+    let h_1 = g.child(hash(&[1]), 1) ;
+    let h_2 = g.child(hash(&[2]), 2) ;
+
+    // Whichever header hashes lower makes its chain the winner.
+    let (low, high) = if hash(&h_1) < hash(&h_2) { (h_1, h_2) } else { (h_2, h_1) } ;
+    let low_chain = &[g.clone(), low] ;
+    let high_chain = &[g, high] ;
+
+    assert!(SmallestHashSumRule::first_chain_is_better(low_chain, high_chain)) ;
+    assert!(!SmallestHashSumRule::first_chain_is_better(high_chain, low_chain)) ;
+
+    assert_eq!(SmallestHashSumRule::best_chain(&[high_chain, low_chain]), low_chain) ;
+    assert_eq!(SmallestHashSumRule::best_chain(&[low_chain, high_chain]), low_chain) ;
+}
+
+#[test]
+fn bc_5_last_common_ancestor_of_fork() {
+    let (prefix, longest_suffix, heaviest_suffix) = create_fork_one_side_longer_other_side_heavier() ;
+    let longest_chain = [&prefix[..], &longest_suffix].concat() ;
+    let heaviest_chain = [&prefix[..], &heaviest_suffix].concat() ;
+
+    assert_eq!(last_common_ancestor(&longest_chain, &heaviest_chain), Some(prefix.len() - 1)) ;
+    assert_eq!(last_common_ancestor(&heaviest_chain, &longest_chain), Some(prefix.len() - 1)) ;
+
+    // A chain shares its whole self with its own prefix.
+    assert_eq!(last_common_ancestor(&longest_chain, &prefix), Some(prefix.len() - 1)) ;
+}
+
+#[test]
+fn bc_5_last_common_ancestor_of_disjoint_chains() {
+    let (prefix, longest_suffix, heaviest_suffix) = create_fork_one_side_longer_other_side_heavier() ;
+
+    assert_eq!(last_common_ancestor(&longest_suffix, &heaviest_suffix), None) ;
+    assert_eq!(last_common_ancestor(&prefix, &[]), None) ;
+}
+
+#[test]
+fn bc_5_render_two_forks() {
+    let g = Header::genesis() ;
+    let b1 = g.child(hash(&vec![1]), 1) ;
+    let b2 = b1.child(hash(&vec![2]), 3) ;
+    let b3 = b2.child(hash(&vec![3]), 6) ;
+    let b4 = b3.child(hash(&vec![4]), 10) ;
+    let b3_prime = b2.child(hash(&vec![5]), 8) ;
+    let b4_prime = b3_prime.child(hash(&vec![6]), 14) ;
+
+    let chain_1 = [g.clone(), b1.clone(), b2.clone(), b3, b4] ;
+    let chain_2 = [g, b1, b2, b3_prime, b4_prime] ;
+
+    let expected = concat!(
+        "G -- 1 -- 2 -- 3 -- 4\n",
+        "           \\-- 3' -- 4'",
+    ) ;
+    assert_eq!(render_forks(&[&chain_1, &chain_2]), expected) ;
+}
+
+#[test]
+fn bc_5_render_single_chain() {
+    let g = Header::genesis() ;
+    let b1 = g.child(hash(&vec![1]), 1) ;
+
+    assert_eq!(render_forks(&[&[g, b1]]), "G -- 1") ;
+    assert_eq!(render_forks(&[]), "") ;
+}
+
+#[test]
+fn bc_5_most_extrinsics_rule() {
+    let g = Block::genesis() ;
+
+    let a1 = g.child(vec![1, 2, 3]) ;
+    let a2 = a1.child(vec![4]) ;
+    let chain_1 = &[g.clone(), a1, a2] ;
+
+    let b1 = g.child(vec![5]) ;
+    let b2 = b1.child(vec![6, 7]) ;
+    let chain_2 = &[g, b1, b2] ;
+
+    assert!(MostExtrinsicsRule::first_chain_is_better(chain_1, chain_2)) ;
+    assert!(!MostExtrinsicsRule::first_chain_is_better(chain_2, chain_1)) ;
+    assert_eq!(MostExtrinsicsRule::best_chain(&[chain_2, chain_1]), chain_1) ;
+}
+
+#[test]
+fn bc_5_mine_for_parity() {
+    let g = Header::genesis() ;
+    for state in 0..20 {
+        let mut even_header = g.child(state, state) ;
+        mine_for_parity(&mut even_header, true) ;
+        assert_eq!(hash(&even_header) % 2, 0) ;
+
+        let mut odd_header = g.child(state, state) ;
+        mine_for_parity(&mut odd_header, false) ;
+        assert_eq!(hash(&odd_header) % 2, 1) ;
+    }
+}
+
+#[test]
+fn bc_5_fork_choice_on_block_chains() {
+    let longer = Block::build_chain(&[vec![1], vec![2], vec![3]]) ;
+    let shorter = Block::build_chain(&[vec![4]]) ;
+
+    let longer_headers = super::p4_batched_extrinsics::headers_of(&longer) ;
+    let shorter_headers = super::p4_batched_extrinsics::headers_of(&shorter) ;
+
+    assert!(LongestChainRule::first_chain_is_better(&longer_headers, &shorter_headers)) ;
+    assert!(!LongestChainRule::first_chain_is_better(&shorter_headers, &longer_headers)) ;
+}
+
+#[test]
+fn bc_5_best_valid_chain_skips_tampered_chain() {
+    let valid = super::p4_batched_extrinsics::headers_of(&Block::build_chain(&[vec![1], vec![2]])) ;
+    let mut tampered = super::p4_batched_extrinsics::headers_of(&Block::build_chain(&[vec![3], vec![4], vec![5]])) ;
+    tampered[2] = tampered[1].clone() ;
+
+    // The tampered chain is longer, but it isn't valid.
+    assert_eq!(LongestChainRule::best_chain(&[&valid, &tampered]), &tampered[..]) ;
+    assert_eq!(LongestChainRule::best_valid_chain(&[&valid, &tampered]), Some(&valid[..])) ;
+}
+
+#[test]
+fn bc_5_best_valid_chain_none_valid() {
+    let mut tampered = super::p4_batched_extrinsics::headers_of(&Block::build_chain(&[vec![3], vec![4]])) ;
+    tampered.swap(1, 2) ;
+
+    assert_eq!(LongestChainRule::best_valid_chain(&[&tampered, &[]]), None) ;
+}
+
+#[test]
+fn bc_5_variable_difficulty_short_hard_chain_wins() {
+    let g = Header::genesis() ;
+
+    // A long chain of easy blocks, each worth less than `u64::MAX / 100`.
+    let mut easy_chain = vec![g.clone()] ;
+    for i in 0..6 {
+        let mut header = easy_chain.last().unwrap().child(hash(&[i]), 0) ;
+        mine_consensus_digest(&mut header, u64::MAX / 100) ;
+        easy_chain.push(header) ;
+    }
+
+    // A short chain of blocks mined far below their threshold, each worth more than
+    // `u64::MAX / 2 - u64::MAX / 100`.
+    let mut hard_chain = vec![g] ;
+    for i in 0..2 {
+        let mut header = hard_chain.last().unwrap().child(hash(&[i]), 0) ;
+        header.threshold = u64::MAX / 2 ;
+        while hash(&header) >= u64::MAX / 100 {
+            header.consensus_digest += 1 ;
+        }
+        hard_chain.push(header) ;
+    }
+
+    assert!(LongestChainRule::first_chain_is_better(&easy_chain, &hard_chain)) ;
+    assert!(VariableDifficultyHeaviestRule::first_chain_is_better(&hard_chain, &easy_chain)) ;
+    assert!(!VariableDifficultyHeaviestRule::first_chain_is_better(&easy_chain, &hard_chain)) ;
+    assert_eq!(VariableDifficultyHeaviestRule::best_chain(&[&easy_chain, &hard_chain]), &hard_chain[..]) ;
+}
+
+#[test]
+fn bc_5_rank_three_chains() {
+    let g = Header::genesis() ;
+    let h_a1 = g.child(hash(&[1]), 1) ;
+    let chain_1 = &[g.clone(), h_a1.clone()] ;
+
+    let h_a2 = h_a1.child(hash(&[2]), 2) ;
+    let h_a3 = h_a2.child(hash(&[3]), 3) ;
+    let chain_2 = &[g.clone(), h_a1.clone(), h_a2.clone(), h_a3] ;
+
+    let chain_3 = &[g, h_a1, h_a2] ;
+
+    let ranked = LongestChainRule::rank_chains(&[chain_1, chain_2, chain_3]) ;
+    assert_eq!(ranked, vec![&chain_2[..], &chain_3[..], &chain_1[..]]) ;
+}
+
+#[test]
+fn bc_5_summarize_chain() {
+    let g = Header::genesis() ;
+    let mut h1 = g.child(hash(&[1]), 1) ;
+    mine_for_parity(&mut h1, true) ;
+    let mut h2 = h1.child(hash(&[2]), 3) ;
+    mine_for_parity(&mut h2, false) ;
+    let mut h3 = h2.child(hash(&[3]), 6) ;
+    mine_consensus_digest(&mut h3, u64::MAX / 4) ;
+    let chain = [g.clone(), h1, h2, h3.clone()] ;
+
+    let summary = summarize(&chain) ;
+    assert_eq!(summary.length, 4) ;
+    assert_eq!(summary.total_work, VariableDifficultyHeaviestRule::get_work(&chain)) ;
+    assert_eq!(summary.tip_hash, hash(&h3)) ;
+    let even_others = [&g, &h3].iter().filter(|header| hash(header) % 2 == 0).count() ;
+    assert_eq!(summary.even_blocks, 1 + even_others) ;
+    assert_eq!(summary.even_blocks, MostBlocksWithEvenHash::count_even_hashes(&chain)) ;
+}
+
+#[test]
+fn bc_5_summarize_empty_chain() {
+    let summary = summarize(&[]) ;
+    assert_eq!(summary, ChainSummary { length: 0, total_work: 0, tip_hash: 0, even_blocks: 0 }) ;
+}
+
+#[test]
+fn bc_5_heaviest_chain_depends_on_threshold() {
+    let g = Header::genesis() ;
+
+    // One block mined below the threshold.
+    let mut a1 = g.child(hash(&[1]), 1) ;
+    mine_consensus_digest(&mut a1, THRESHOLD) ;
+    let chain_1 = [g.clone(), a1] ;
+
+    // Two blocks whose hashes are above the threshold, but below half of the hash space.
+    let mut b1 = g.child(hash(&[2]), 2) ;
+    while !(THRESHOLD..u64::MAX / 2).contains(&hash(&b1)) {
+        b1.consensus_digest += 1 ;
+    }
+    let mut b2 = b1.child(hash(&[3]), 5) ;
+    while !(THRESHOLD..u64::MAX / 2).contains(&hash(&b2)) {
+        b2.consensus_digest += 1 ;
+    }
+    let chain_2 = [g, b1, b2] ;
+
+    // Against the usual threshold the extra blocks carry negative work, so the short chain wins.
+    assert!(HeaviestChainRule::first_chain_is_better(&chain_1, &chain_2)) ;
+    assert!(!HeaviestChainRule::first_chain_is_better(&chain_2, &chain_1)) ;
+    assert_eq!(HeaviestChainRule::best_chain(&[&chain_2, &chain_1]), &chain_1[..]) ;
+    assert_eq!(HeaviestChainRule::rank_chains(&[&chain_2, &chain_1]), vec![&chain_1[..], &chain_2[..]]) ;
+
+    // Against the loosest possible threshold every block counts, and the long chain wins.
+    type Loose = ThresholdHeaviestRule<{ u64::MAX }> ;
+    assert!(Loose::first_chain_is_better(&chain_2, &chain_1)) ;
+    assert!(!Loose::first_chain_is_better(&chain_1, &chain_2)) ;
+    assert_eq!(Loose::best_chain(&[&chain_1, &chain_2]), &chain_2[..]) ;
+    assert_eq!(Loose::rank_chains(&[&chain_1, &chain_2]), vec![&chain_2[..], &chain_1[..]]) ;
+}
+
+#[test]
+fn bc_5_confirmations_in_five_block_chain() {
+    let g = Header::genesis() ;
+    let h1 = g.child(hash(&[1]), 1) ;
+    let h2 = h1.child(hash(&[2]), 3) ;
+    let h3 = h2.child(hash(&[3]), 6) ;
+    let h4 = h3.child(hash(&[4]), 10) ;
+    let chain = [g.clone(), h1.clone(), h2, h3.clone(), h4.clone()] ;
+
+    assert_eq!(confirmations(&chain, &g), Some(4)) ;
+    assert_eq!(confirmations(&chain, &h1), Some(3)) ;
+    assert_eq!(confirmations(&chain, &h3), Some(1)) ;
+    assert_eq!(confirmations(&chain, &h4), Some(0)) ;
+
+    // A sibling of the tip isn't part of the chain.
+    let h4_prime = h3.child(hash(&[5]), 11) ;
+    assert_eq!(confirmations(&chain, &h4_prime), None) ;
+    assert_eq!(confirmations(&[], &g), None) ;
+}
+
+#[test]
+fn bc_5_is_extension_of() {
+    let g = Header::genesis() ;
+    let h1 = g.child(hash(&[1]), 1) ;
+    let h2 = h1.child(hash(&[2]), 3) ;
+    let h3 = h2.child(hash(&[3]), 6) ;
+    let h2_prime = h1.child(hash(&[4]), 5) ;
+    let h3_prime = h2_prime.child(hash(&[5]), 10) ;
+
+    let base = [g.clone(), h1.clone(), h2.clone()] ;
+    let extended = [g.clone(), h1.clone(), h2, h3] ;
+    let divergent = [g.clone(), h1, h2_prime, h3_prime] ;
+
+    // A genuine extension.
+    assert!(is_extension_of(&base, &extended)) ;
+    assert!(is_extension_of(&[], &base)) ;
+    // An equal chain adds nothing.
+    assert!(!is_extension_of(&base, &base)) ;
+    // A divergent chain would need the base's tip retracted.
+    assert!(!is_extension_of(&base, &divergent)) ;
+    // A shorter chain never extends a longer one.
+    assert!(!is_extension_of(&extended, &base)) ;
+}
@@ -21,8 +21,19 @@ use super::p3_consensus::THRESHOLD ;
 /// remember that in real world blockchains, the state is often really really large.
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct State {
-    sum: u64,
-    product: u64,
+    pub(crate) sum: u64,
+    pub(crate) product: u64,
+}
+
+/// Compute the state root that headers commit to. Rather than handing the whole struct to the
+/// hasher, the fields are encoded one after the other in a fixed order first. This way the root
+/// only depends on what the state contains, which will matter once the state holds maps or sets
+/// that don't iterate in a fixed order.
+pub(crate) fn state_root(state: &State) -> Hash {
+    let mut encoded = Vec::with_capacity(16) ;
+    encoded.extend_from_slice(&state.sum.to_le_bytes()) ;
+    encoded.extend_from_slice(&state.product.to_le_bytes()) ;
+    hash(&encoded)
 }
 
 /// The header no longer contains the state directly, but rather, it contains a hash of 
@@ -31,13 +42,13 @@ pub struct State {
 /// author's state.
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct Header {
-    parent: Hash,
-    height: u64,
-    extrinsics_root: Hash,
+    pub(crate) parent: Hash,
+    pub(crate) height: u64,
+    pub(crate) extrinsics_root: Hash,
     // Stores a cryptographic commitment, like a Merkle root or a hash to the complete
     // post state.
-    state_root: Hash,
-    consensus_digest: u64,
+    pub(crate) state_root: Hash,
+    pub(crate) consensus_digest: u64,
 }
 
 /// Methods for creating and verifying headers.
@@ -62,18 +73,29 @@ impl Header {
     /// 
     /// The state root is passed in similarly to how the complete state
     /// was in the previous section.
+    ///
+    /// The height saturates at `u64::MAX` instead of overflowing.
     fn child(&self, extrinsics_root: Hash, state_root: Hash) -> Self {
         Self {
             parent: hash(self),
-            height: self.height + 1,
+            height: self.height.saturating_add(1),
             extrinsics_root,
             state_root,
             consensus_digest: 0,
         }
     }
 
-    /// Verify a single child header.
-    fn verify_child(&self, child: &Header) -> bool {
+    /// Verify a single child header. Only the parent link and the height are checked here,
+    /// because the state root can't be checked without executing the block body.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let genesis = Header::genesis(state_root(&state));
+    /// let child = genesis.child(hash(&vec![1, 2, 3]), state_root(&post_state));
+    /// assert!(genesis.verify_child(&child));
+    /// ```
+    pub fn verify_child(&self, child: &Header) -> bool {
         let mut is_verified = true ;
         let parent_header = self ;
         is_verified &= hash(parent_header) == child.parent && parent_header.height.saturating_add(1) == child.height ;
@@ -113,18 +135,33 @@ pub struct Block {
 /// calculate state roots to pass to the header-level methods.
 impl Block {
     /// Execute the extrinsics and calculate state.
+    ///
+    /// Both the sum and the product saturate at `u64::MAX` instead of overflowing, so that
+    /// adversarial extrinsics can't make execution panic.
     pub fn execute_extrinsics(pre_state: &mut State, extrinsics: &Vec<u64>) -> State {
-        for extrinsic in extrinsics.iter() {
-            pre_state.sum += *extrinsic ;
-            pre_state.product *= *extrinsic ; 
-        }
+        *pre_state = Block::execute_extrinsics_ref(pre_state, extrinsics) ;
         pre_state.clone()
     }
 
+    /// Execute the extrinsics on top of a borrowed pre-state, and build the post-state from
+    /// scratch. The pre-state is neither modified nor cloned.
+    pub fn execute_extrinsics_ref(pre_state: &State, extrinsics: &[u64]) -> State {
+        State {
+            sum: extrinsics.iter().fold(pre_state.sum, |sum, extrinsic| sum.saturating_add(*extrinsic)),
+            product: extrinsics.iter().fold(pre_state.product, |product, extrinsic| product.saturating_mul(*extrinsic)),
+        }
+    }
+
+    /// Another name for `execute_extrinsics_ref`, which reads better where only the resulting
+    /// state matters. Unlike `execute_extrinsics`, the pre-state is left untouched.
+    pub fn post_state(pre_state: &State, extrinsics: &[u64]) -> State {
+        Block::execute_extrinsics_ref(pre_state, extrinsics)
+    }
+
     /// Returns a valid genesis block. By convention this block has no extrinsics.
     pub fn genesis(genesis_state: &State) -> Self {
         Self {
-            header: Header::genesis(hash(genesis_state)),
+            header: Header::genesis(state_root(genesis_state)),
             body: Vec::new(),
         }
     }
@@ -134,34 +171,81 @@ impl Block {
         Self {
             header: self.header.child(
                 hash(&extrinsics),
-                hash(&Block::execute_extrinsics(&mut pre_state.clone(), &extrinsics))
+                state_root(&Block::execute_extrinsics_ref(pre_state, &extrinsics))
             ),
             body: extrinsics,
         }
     }
 
+    /// Build a chain starting from a genesis block committing to the given genesis state,
+    /// with one child block per batch of extrinsics. Each child is executed on top of the state
+    /// produced by its parent. Returns the full chain including genesis, along with the final
+    /// state so that callers can go on to verify or extend the chain.
+    ///
+    /// Note that once any batch contains a zero extrinsic, the product stays zero for the rest
+    /// of the chain.
+    pub fn build_chain(genesis_state: &State, extrinsic_batches: &[Vec<u64>]) -> (Vec<Block>, State) {
+        let mut chain = vec![Block::genesis(genesis_state)] ;
+        let mut state = genesis_state.clone() ;
+
+        for extrinsics in extrinsic_batches {
+            let child = chain.last().unwrap().child(&state, extrinsics.clone()) ;
+            state = Block::execute_extrinsics_ref(&state, extrinsics) ;
+            chain.push(child) ;
+        }
+        (chain, state)
+    }
+
     /// Verify that all the given blocks form a valid chain from this block to the tip.
     /// 
     /// This time we need to validate the initial block itself by confirming that we
     /// have been given a valid pre-state. And we still need to verify the headers,
     /// execute all transactions, and check the final state.
+    ///
+    /// An empty chain is valid as long as this block itself is valid on top of the pre-state.
     pub fn verify_sub_chain(&self, pre_state: &State, chain: &[Block]) -> bool {
-        let mut prev_block = self ;
-        let mut chain_iter = chain.iter() ;
-        let mut is_verified = true ;
+        // Need to verify that the initial block has a valid pre-state. This only
+        // needs to happen once, after that the state is threaded through the chain.
+        // A genesis block has no extrinsics, so this also rejects a pre-state other than the
+        // genesis state it commits to. Otherwise we'd be verifying against a made up state.
+        if state_root(&Block::execute_extrinsics_ref(pre_state, &self.body)) != self.header.state_root {
+            return false;
+        }
+
+        first_invalid_block(self, pre_state, chain).is_none()
+    }
+}
 
-        while let Some(curr_block) = chain_iter.next() {
-            // Need to verify that the initial block has a valid pre-state.
-            if (hash(&Block::execute_extrinsics(&mut pre_state.clone(), &prev_block.body)) != 
-                prev_block.header.state_root) {
-                    return false;
-            }
-            is_verified &= prev_block.header.verify_child(&curr_block.header) && 
-                hash(&curr_block.body) == curr_block.header.extrinsics_root ;
-            prev_block = curr_block ;
+/// Replay the chain on top of the given starting block, and return the index in `chain` of the
+/// earliest block that fails verification. Returns `None` if every block is valid.
+///
+/// The pre-state is the state before the starting block, just like in `verify_sub_chain`. The
+/// starting block itself is trusted to be valid on top of it. Use `verify_sub_chain` to check
+/// that as well.
+pub fn first_invalid_block(genesis: &Block, pre_state: &State, chain: &[Block]) -> Option<usize> {
+    let mut state = Block::execute_extrinsics_ref(pre_state, &genesis.body) ;
+    let mut prev_block = genesis ;
+    for (index, curr_block) in chain.iter().enumerate() {
+        // Each block is executed on top of the state produced by its predecessor.
+        if !verify_block(&prev_block.header, &state, curr_block) {
+            return Some(index);
         }
-        is_verified
+        state = Block::execute_extrinsics_ref(&state, &curr_block.body) ;
+        prev_block = curr_block ;
     }
+    None
+}
+
+/// Verify a single block on top of its parent's header and the state that header commits to.
+///
+/// The block's header has to be a valid child of the parent, its extrinsics root has to match
+/// the body, and executing the body on the pre-state has to give the state in its state root.
+/// Unlike `verify_sub_chain`, the pre-state isn't checked against the parent's state root, so
+/// the caller must know it is the right one.
+pub fn verify_block(parent: &Header, pre_state: &State, block: &Block) -> bool {
+    parent.verify_child(&block.header)
+        && hash(&block.body) == block.header.extrinsics_root
+        && state_root(&Block::execute_extrinsics_ref(pre_state, &block.body)) == block.header.state_root
 }
 
 /// Create an invalid child block of the given block. The returned block should have an
@@ -176,9 +260,9 @@ impl Block {
 /// As before, you do not need the entire parent block to do this. You only need the header.
 /// You do, however, now need a pre-state as you have throughout much of this section.
 fn build_invalid_child_block_with_valid_header(parent: &Header, pre_state: &State) -> Block {
-    let state = Block::execute_extrinsics(&mut pre_state.clone(), &vec![1, 2, 3, 4, 5]) ;
+    let state = Block::execute_extrinsics_ref(pre_state, &[1, 2, 3, 4, 5]) ;
 
-    let child_header = parent.child(hash(&vec![1, 2, 3, 4, 5]), hash(&state)) ;
+    let child_header = parent.child(hash(&vec![1, 2, 3, 4, 5]), state_root(&state)) ;
 
     let child_block = Block {
         header: child_header,
@@ -191,18 +275,18 @@ fn build_invalid_child_block_with_valid_header(parent: &Header, pre_state: &Stat
 #[test]
 fn bc_6_genesis_header() {
     let state = State { sum: 6, product: 9 } ;
-    let g = Header::genesis(hash(&state)) ;
+    let g = Header::genesis(state_root(&state)) ;
 
     assert_eq!(g.parent, 0) ;
     assert_eq!(g.height, 0) ;
     assert_eq!(g.extrinsics_root, 0) ;
-    assert_eq!(g.state_root, hash(&state)) ;
+    assert_eq!(g.state_root, state_root(&state)) ;
 }
 
 #[test]
 fn bc_6_genesis_block() {
     let state = State { sum: 6, product: 9} ;
-    let gh = Header::genesis(hash(&state)) ;
+    let gh = Header::genesis(state_root(&state)) ;
     let gb = Block::genesis(&state) ;
 
     assert_eq!(gb.header, gh) ;
@@ -248,19 +332,19 @@ fn bc_6_child_block() {
 #[test]
 fn bc_6_child_header() {
     let state_0 = State { sum: 6, product: 9 } ;
-    let g = Header::genesis(hash(&state_0)) ;
+    let g = Header::genesis(state_root(&state_0)) ;
     let mut extrinsics = vec![1, 2, 3] ;
     let mut state_1 = state_0 ;
     for extrinsic in extrinsics.iter() {
         state_1.sum += extrinsic ;
         state_1.product *= extrinsic ;
     }
-    let h1 = g.child(hash(&extrinsics), hash(&state_1)) ;
+    let h1 = g.child(hash(&extrinsics), state_root(&state_1)) ;
 
     assert_eq!(h1.height, 1) ;
     assert_eq!(h1.parent, hash(&g)) ;
     assert_eq!(h1.extrinsics_root, hash(&extrinsics)) ;
-    assert_eq!(h1.state_root, hash(&state_1)) ;
+    assert_eq!(h1.state_root, state_root(&state_1)) ;
 
     extrinsics = vec![10, 20] ;
     let mut state_2 = state_1 ;
@@ -269,12 +353,12 @@ fn bc_6_child_header() {
         state_2.product *= extrinsic ;
     }
 
-    let h2 = h1.child(hash(&extrinsics), hash(&state_2)) ;
+    let h2 = h1.child(hash(&extrinsics), state_root(&state_2)) ;
 
     assert_eq!(h2.height, 2) ;
     assert_eq!(h2.parent, hash(&h1)) ;
     assert_eq!(h2.extrinsics_root, hash(&extrinsics)) ;
-    assert_eq!(h2.state_root, hash(&state_2)) ;
+    assert_eq!(h2.state_root, state_root(&state_2)) ;
 }
 
 #[test]
@@ -291,12 +375,12 @@ fn bc_6_verify_three_blocks() {
 #[test]
 fn bc_6_invalid_header_doesnt_check() {
     let state = State { sum: 6, product: 9 } ;
-    let g = Header::genesis(hash(&state)) ;
+    let g = Header::genesis(state_root(&state)) ;
     let h1 = Header {
         parent: 0,
         height: 100,
         extrinsics_root: 0,
-        state_root: hash(&(State { sum: 0, product: 0 })),
+        state_root: state_root(&(State { sum: 0, product: 0 })),
         consensus_digest: 0,
     } ;
 
@@ -318,7 +402,7 @@ fn bc_6_block_with_invalid_header_doesnt_check() {
     let state = State { sum: 6, product: 9 } ;
     let b0 = Block::genesis(&state) ;
     let mut b1 = b0.child(&state, vec![1, 2, 3]) ;
-    b1.header = Header::genesis(hash(&state)) ;
+    b1.header = Header::genesis(state_root(&state)) ;
 
     assert!(!b0.verify_sub_chain(&state, &[b1])) ;
 }
@@ -337,4 +421,158 @@ fn bc_6_student_invalid_block_really_is_invalid() {
 
     // Make sure that the block is not valid when executed.
     assert!(!gb.verify_sub_chain(&state, &[b1])) ;
-}
\ No newline at end of file
+}
+
+#[test]
+fn bc_6_verify_child_directly() {
+    let state = State { sum: 6, product: 9 } ;
+    let b0 = Block::genesis(&state) ;
+    let b1 = b0.child(&state, vec![1, 2, 3]) ;
+
+    assert!(b0.header.verify_child(&b1.header)) ;
+    assert!(!b1.header.verify_child(&b0.header)) ;
+}
+
+#[test]
+fn bc_6_build_chain_with_zero_extrinsic() {
+    let genesis_state = State { sum: 6, product: 9 } ;
+    let (chain, final_state) = Block::build_chain(&genesis_state, &[vec![1, 2], vec![3, 0], vec![4]]) ;
+
+    assert_eq!(chain.len(), 4) ;
+    assert_eq!(chain[0], Block::genesis(&genesis_state)) ;
+
+    // The zero in the second batch wipes out the product for good.
+    assert_eq!(final_state, State { sum: 16, product: 0 }) ;
+    assert_eq!(chain[3].header.state_root, state_root(&final_state)) ;
+    assert_eq!(chain[2].header.state_root, state_root(&State { sum: 12, product: 0 })) ;
+
+    for window in chain.windows(2) {
+        assert!(window[0].header.verify_child(&window[1].header)) ;
+    }
+}
+
+#[test]
+fn bc_6_execution_saturates_instead_of_overflowing() {
+    let mut state = State { sum: u64::MAX - 1, product: u64::MAX / 2 } ;
+    let post_state = Block::execute_extrinsics(&mut state, &vec![3, 5]) ;
+
+    assert_eq!(post_state, State { sum: u64::MAX, product: u64::MAX }) ;
+}
+
+#[test]
+fn bc_6_post_state_leaves_pre_state_untouched() {
+    let pre_state = State { sum: 2, product: 3 } ;
+    let extrinsics = vec![4, 5] ;
+
+    let post_state = Block::post_state(&pre_state, &extrinsics) ;
+
+    assert_eq!(pre_state, State { sum: 2, product: 3 }) ;
+    assert_eq!(post_state, State { sum: 11, product: 60 }) ;
+    assert_eq!(post_state, Block::execute_extrinsics(&mut pre_state.clone(), &extrinsics)) ;
+    assert_eq!(post_state, Block::execute_extrinsics_ref(&pre_state, &extrinsics)) ;
+}
+
+#[test]
+fn bc_6_verify_threads_state_through_chain() {
+    let genesis_state = State { sum: 6, product: 9 } ;
+    let (chain, _) = Block::build_chain(&genesis_state, &[vec![1], vec![2], vec![3]]) ;
+    assert!(chain[0].verify_sub_chain(&genesis_state, &chain[1..])) ;
+
+    // Build the middle block against the genesis state instead of the state after block 1.
+    // Its header is fine, and its state root would even match if every block were checked
+    // against the genesis state, but it is not the real post state.
+    let b1 = chain[1].clone() ;
+    let b2 = b1.child(&genesis_state, vec![2]) ;
+    let state_2 = Block::execute_extrinsics_ref(&genesis_state, &[2]) ;
+    let b3 = b2.child(&state_2, vec![3]) ;
+
+    assert!(b1.header.verify_child(&b2.header)) ;
+    assert!(b2.header.verify_child(&b3.header)) ;
+    assert!(!chain[0].verify_sub_chain(&genesis_state, &[b1, b2, b3])) ;
+}
+
+#[test]
+fn bc_6_verify_rejects_fabricated_genesis_state() {
+    let genesis_state = State { sum: 6, product: 9 } ;
+    let (chain, _) = Block::build_chain(&genesis_state, &[vec![1, 2]]) ;
+
+    let fabricated_state = State { sum: 100, product: 100 } ;
+    assert_ne!(state_root(&fabricated_state), chain[0].header.state_root) ;
+
+    assert!(!chain[0].verify_sub_chain(&fabricated_state, &chain[1..])) ;
+    assert!(!chain[0].verify_sub_chain(&fabricated_state, &[])) ;
+}
+
+#[test]
+fn bc_6_execute_extrinsics_ref_matches_step_by_step_execution() {
+    let batches = [vec![], vec![2, 3, 4], vec![0, 7], vec![u64::MAX, u64::MAX]] ;
+    for batch in batches {
+        let pre_state = State { sum: 5, product: 6 } ;
+        // Execute the batch step by step on a copy, the way blocks used to be executed.
+        let mut expected = pre_state.clone() ;
+        for extrinsic in &batch {
+            expected.sum = expected.sum.saturating_add(*extrinsic) ;
+            expected.product = expected.product.saturating_mul(*extrinsic) ;
+        }
+
+        assert_eq!(Block::execute_extrinsics_ref(&pre_state, &batch), expected) ;
+        assert_eq!(pre_state, State { sum: 5, product: 6 }) ;
+    }
+}
+
+#[test]
+fn bc_6_verify_single_block() {
+    let genesis_state = State { sum: 6, product: 7 } ;
+    let g = Block::genesis(&genesis_state) ;
+    let b1 = g.child(&genesis_state, vec![1, 2]) ;
+
+    assert!(verify_block(&g.header, &genesis_state, &b1)) ;
+}
+
+#[test]
+fn bc_6_verify_block_with_wrong_state_root() {
+    let genesis_state = State { sum: 6, product: 7 } ;
+    let g = Block::genesis(&genesis_state) ;
+    let mut b1 = g.child(&genesis_state, vec![1, 2]) ;
+    b1.header.state_root = state_root(&State { sum: 9, product: 0 }) ;
+
+    assert!(g.header.verify_child(&b1.header)) ;
+    assert!(!verify_block(&g.header, &genesis_state, &b1)) ;
+}
+
+#[test]
+fn bc_6_state_root_depends_only_on_contents() {
+    let start = State { sum: 0, product: 1 } ;
+    let forwards = Block::execute_extrinsics_ref(&start, &[2, 3, 4]) ;
+    let backwards = Block::execute_extrinsics_ref(&start, &[4, 3, 2]) ;
+
+    assert_eq!(state_root(&forwards), state_root(&backwards)) ;
+    assert_eq!(state_root(&forwards), state_root(&State { sum: 9, product: 24 })) ;
+    assert_ne!(state_root(&forwards), state_root(&State { sum: 24, product: 9 })) ;
+}
+
+#[test]
+fn bc_6_empty_chain_is_valid() {
+    let genesis_state = State { sum: 0, product: 1 } ;
+    let (chain, _) = Block::build_chain(&genesis_state, &[vec![2, 3]]) ;
+
+    assert!(chain[0].header.verify_sub_chain(&[])) ;
+    assert!(chain[0].verify_sub_chain(&genesis_state, &[])) ;
+
+    // The pre-state of a later block is the state its parent left behind.
+    assert!(chain[1].header.verify_sub_chain(&[])) ;
+    assert!(chain[1].verify_sub_chain(&genesis_state, &[])) ;
+}
+
+#[test]
+fn bc_6_first_invalid_block_finds_tampered_block() {
+    let genesis_state = State { sum: 0, product: 1 } ;
+    let (chain, _) = Block::build_chain(&genesis_state, &[vec![1], vec![2], vec![3], vec![4]]) ;
+    assert_eq!(first_invalid_block(&chain[0], &genesis_state, &chain[1..]), None) ;
+
+    // Tamper with the body of the block at height 2, which is index 1 of the sub-chain.
+    let mut tampered = chain.clone() ;
+    tampered[2].body = vec![5] ;
+    assert_eq!(first_invalid_block(&tampered[0], &genesis_state, &tampered[1..]), Some(1)) ;
+    assert!(!tampered[0].verify_sub_chain(&genesis_state, &tampered[1..])) ;
+}
@@ -0,0 +1,226 @@
+//! Proof of Work lets anybody author a block as long as they burn enough energy. Proof of
+//! Authority instead gives a fixed set of authorities the right to author, and takes turns
+//! between them. Each slot (here, each height) has a designated primary author. If the primary
+//! fails to show up, the next authority in line may step in as a secondary author.
+//!
+//! With authorship in place we can finally write the fork choice rule that
+//! `MostBlocksWithEvenHash` was standing in for: the best chain is the one with the most
+//! primary-authored blocks.
+//!
+//! Real PoA puts a cryptographic signature in the consensus digest. We use the same toy scheme
+//! as the signed extrinsics lesson: the signature is the hash of the author and the unsealed header.
+
+use super::p4_batched_extrinsics::{Block, Header};
+use super::p5_fork_choice::{ForkChoice, HeaviestChainRule};
+use crate::c1_state_machine::User;
+use crate::hash;
+
+type Hash = u64;
+
+/// The authorities that take turns authoring blocks, in order.
+const AUTHORITIES: [User; 3] = [User::Alice, User::Bob, User::Charlie];
+
+/// The authority that is supposed to author the block at the given height.
+pub fn primary_author(height: u64) -> User {
+    AUTHORITIES[(height % AUTHORITIES.len() as u64) as usize]
+}
+
+/// The authority that may author the block at the given height if the primary doesn't. This
+/// is the authority that comes after the primary in the rotation.
+pub fn secondary_author(height: u64) -> User {
+    // Reduce the height first, so the highest possible height doesn't overflow.
+    let slot = height % AUTHORITIES.len() as u64;
+    AUTHORITIES[((slot + 1) % AUTHORITIES.len() as u64) as usize]
+}
+
+/// The toy signature of the given author over a header, ignoring whatever digest it already has.
+fn signature(header: &Header, author: User) -> u64 {
+    let mut unsealed = header.clone();
+    unsealed.consensus_digest = 0;
+    hash(&(author, hash(&unsealed)))
+}
+
+/// Sign the header as the given author by putting the signature in the consensus digest.
+pub fn seal(header: &mut Header, author: User) {
+    header.consensus_digest = signature(header, author);
+}
+
+/// Recover which authority sealed the header, if any of them did.
+pub fn author_of(header: &Header) -> Option<User> {
+    AUTHORITIES
+        .iter()
+        .copied()
+        .find(|author| header.consensus_digest == signature(header, *author))
+}
+
+/// Whether the header was sealed by the primary author of its slot.
+pub fn is_primary_authored(header: &Header) -> bool {
+    author_of(header) == Some(primary_author(header.height()))
+}
+
+/// Find every pair of distinct blocks that the same authority sealed at the same height. An
+/// honest authority authors at most one block per slot, so each pair is proof that its author
+/// equivocated, which is what real PoA and PoS chains slash for. The pairs are returned as
+/// header hashes, in the order the blocks were given. Unsealed blocks are ignored.
+pub fn find_equivocations(blocks: &[Block]) -> Vec<(Hash, Hash)> {
+    let mut equivocations = Vec::new();
+
+    for (index, first) in blocks.iter().enumerate() {
+        let Some(author) = author_of(&first.header) else {
+            continue;
+        };
+        for second in &blocks[index + 1..] {
+            if second.header.height() == first.header.height()
+                && author_of(&second.header) == Some(author)
+                && second.header != first.header
+            {
+                equivocations.push((hash(&first.header), hash(&second.header)));
+            }
+        }
+    }
+    equivocations
+}
+
+/// The best chain is the one with the most primary-authored blocks. Ties are broken by the
+/// most accumulated work.
+pub struct MostPrimaryAuthoredRule;
+
+impl MostPrimaryAuthoredRule {
+    /// Count the blocks that were sealed by their slot's primary author.
+    fn count_primary_authored(chain: &[Header]) -> usize {
+        chain
+            .iter()
+            .filter(|header| is_primary_authored(header))
+            .count()
+    }
+}
+
+impl ForkChoice for MostPrimaryAuthoredRule {
+    fn first_chain_is_better(chain_1: &[Header], chain_2: &[Header]) -> bool {
+        let count_1 = MostPrimaryAuthoredRule::count_primary_authored(chain_1);
+        let count_2 = MostPrimaryAuthoredRule::count_primary_authored(chain_2);
+        if count_1 != count_2 {
+            return count_1 > count_2;
+        }
+        HeaviestChainRule::first_chain_is_better(chain_1, chain_2)
+    }
+
+    fn best_chain<'a>(candidate_chains: &[&'a [Header]]) -> &'a [Header] {
+        let mut chain_iter = candidate_chains.iter();
+        let mut best_chain = chain_iter.next().unwrap();
+
+        for next_chain in chain_iter {
+            if !MostPrimaryAuthoredRule::first_chain_is_better(best_chain, next_chain) {
+                best_chain = next_chain;
+            }
+        }
+        best_chain
+    }
+}
+
+/// Build a child block and seal it as the given author.
+fn sealed_child(parent: &Block, extrinsics: Vec<u64>, author: User) -> Block {
+    let mut block = parent.child(extrinsics);
+    seal(&mut block.header, author);
+    block
+}
+
+#[cfg(test)]
+#[test]
+fn bc_7_authors_take_turns() {
+    assert_eq!(primary_author(0), User::Alice);
+    assert_eq!(primary_author(1), User::Bob);
+    assert_eq!(primary_author(2), User::Charlie);
+    assert_eq!(primary_author(3), User::Alice);
+    assert_eq!(secondary_author(1), User::Charlie);
+}
+
+#[test]
+fn bc_7_recover_author_of_sealed_header() {
+    let g = Block::genesis();
+    let b1 = sealed_child(&g, vec![1], User::Charlie);
+
+    assert_eq!(author_of(&b1.header), Some(User::Charlie));
+    assert!(!is_primary_authored(&b1.header));
+}
+
+#[test]
+fn bc_7_unsealed_header_has_no_author() {
+    let g = Block::genesis();
+    let b1 = g.child(vec![1]);
+
+    assert_eq!(author_of(&b1.header), None);
+}
+
+#[test]
+fn bc_7_fully_primary_chain_beats_chain_with_secondaries() {
+    let g = Block::genesis();
+
+    let a1 = sealed_child(&g, vec![1], primary_author(1));
+    let a2 = sealed_child(&a1, vec![2], primary_author(2));
+    let a3 = sealed_child(&a2, vec![3], primary_author(3));
+    let primary_chain = &[g.header.clone(), a1.header, a2.header, a3.header];
+
+    // The same length, but the primary missed slot 2 and a secondary filled in.
+    let b1 = sealed_child(&g, vec![4], primary_author(1));
+    let b2 = sealed_child(&b1, vec![5], secondary_author(2));
+    let b3 = sealed_child(&b2, vec![6], primary_author(3));
+    let secondary_chain = &[g.header, b1.header, b2.header, b3.header];
+
+    assert!(MostPrimaryAuthoredRule::first_chain_is_better(
+        primary_chain,
+        secondary_chain
+    ));
+    assert!(!MostPrimaryAuthoredRule::first_chain_is_better(
+        secondary_chain,
+        primary_chain
+    ));
+    assert_eq!(
+        MostPrimaryAuthoredRule::best_chain(&[secondary_chain, primary_chain]),
+        primary_chain
+    );
+}
+
+#[test]
+fn bc_7_equal_primary_count_falls_back_to_work() {
+    let g = Block::genesis();
+
+    let a1 = sealed_child(&g, vec![1], primary_author(1));
+    let chain_1 = &[g.header.clone(), a1.header];
+
+    let b1 = sealed_child(&g, vec![2], primary_author(1));
+    let chain_2 = &[g.header, b1.header];
+
+    assert_eq!(
+        MostPrimaryAuthoredRule::first_chain_is_better(chain_1, chain_2),
+        HeaviestChainRule::first_chain_is_better(chain_1, chain_2)
+    );
+    assert_eq!(
+        MostPrimaryAuthoredRule::first_chain_is_better(chain_2, chain_1),
+        HeaviestChainRule::first_chain_is_better(chain_2, chain_1)
+    );
+}
+
+#[test]
+fn bc_7_equivocating_author_is_flagged() {
+    let g = Block::genesis();
+    let a1 = sealed_child(&g, vec![1], primary_author(1));
+    let b1 = sealed_child(&g, vec![2], primary_author(1));
+    let c1 = sealed_child(&g, vec![3], secondary_author(1));
+    let a2 = sealed_child(&a1, vec![4], primary_author(2));
+
+    assert_eq!(
+        find_equivocations(&[a1.clone(), c1.clone(), a2.clone(), b1.clone()]),
+        vec![(hash(&a1.header), hash(&b1.header))]
+    );
+    // The same block seen twice, or different authors at one height, aren't equivocations.
+    assert!(find_equivocations(&[a1.clone(), a1, c1, a2]).is_empty());
+}
+
+#[test]
+fn bc_7_secondary_author_at_highest_height() {
+    for height in [0, 1, 2, u64::MAX - 1, u64::MAX] {
+        assert_ne!(secondary_author(height), primary_author(height));
+        assert_eq!(secondary_author(height), primary_author(height % 3 + 1));
+    }
+}
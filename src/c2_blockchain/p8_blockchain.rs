@@ -0,0 +1,490 @@
+//! So far we have verified chains that were handed to us as a whole. A real node instead
+//! receives blocks one at a time, from many peers, and has to store them as they come in. The
+//! blocks it stores form a tree rooted at genesis, because different authors may build on the
+//! same parent.
+//!
+//! In this lesson we build that block store on top of the rich-state blocks, and secure it with
+//! proof of work. A block is only imported if it was mined below the PoW threshold and builds on
+//! a block that is already known.
+//!
+//! The store also keeps the post-state of every block it imports. Each new block is executed
+//! once, on top of its parent's state, no matter which fork the parent is on. Switching to
+//! another fork never means executing all the way from genesis again.
+//!
+//! Finally, the store tracks which blocks are final. The best chain is the longest one, and a
+//! block on it becomes final once enough blocks have been built on top of it. After that, no
+//! fork can take its place, however long the fork grows.
+
+use std::collections::{HashMap, HashSet};
+
+use super::p3_consensus::THRESHOLD;
+use super::p5_fork_choice::ThresholdHeaviestRule;
+use super::p6_rich_state::{state_root, Block, State};
+use crate::hash;
+
+type Hash = u64;
+
+/// How many blocks have to be built on top of a block before it is final, unless the store is
+/// created with a different number.
+pub const DEFAULT_CONFIRMATIONS: usize = 6;
+
+/// The height of the highest final block, given the length of the best chain including genesis.
+/// A block is final once at least `confirmations` blocks are built on top of it. Returns `None`
+/// if the chain isn't long enough for any block to be final yet.
+pub fn finalized_height(best_chain_len: usize, confirmations: usize) -> Option<u64> {
+    let tip_height = best_chain_len.checked_sub(1)?;
+    tip_height.checked_sub(confirmations).map(|height| height as u64)
+}
+
+/// The reasons a block can be refused by `Blockchain::import_block`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ImportError {
+    /// The hash of the block's header isn't below the PoW threshold.
+    InsufficientWork,
+    /// A block with the same header hash has already been imported.
+    DuplicateBlock,
+    /// The block builds on a parent with the enclosed hash, which hasn't been imported.
+    UnknownParent(Hash),
+    /// The block's height isn't one more than its parent's.
+    HeightMismatch,
+    /// The block's extrinsics root doesn't match its body.
+    BadExtrinsicsRoot,
+    /// Executing the block on top of its parent's state doesn't give the state in its state root.
+    BadStateRoot,
+}
+
+/// A store of all the blocks a node has imported, keyed by the hash of their header.
+pub struct Blockchain {
+    /// Every imported block, including genesis.
+    blocks: HashMap<Hash, Block>,
+    /// The hash of the genesis block that every other block descends from.
+    genesis_hash: Hash,
+    /// The state after every imported block, keyed by the block's header hash.
+    states: HashMap<Hash, State>,
+    /// How many blocks have been executed so far. Each import executes exactly one block.
+    executed_blocks: u64,
+    /// The tip of the best chain, which is the longest one. Ties go to the tip imported first.
+    best_tip: Hash,
+    /// How many blocks have to be built on top of a block on the best chain before it is final.
+    confirmations: usize,
+    /// Every final block. They always form a single chain starting at genesis.
+    finalized: HashSet<Hash>,
+    /// The highest final block. The best chain always goes through it.
+    last_finalized: Hash,
+}
+
+impl Blockchain {
+    /// Create a store holding only the genesis block for the given genesis state. The genesis
+    /// block is trusted as it is, so it doesn't need to be mined.
+    pub fn new(genesis_state: &State) -> Self {
+        Self::with_confirmations(genesis_state, DEFAULT_CONFIRMATIONS)
+    }
+
+    /// Create a store like `new`, in which blocks become final after the given number of
+    /// confirmations rather than `DEFAULT_CONFIRMATIONS`. Genesis is always final.
+    pub fn with_confirmations(genesis_state: &State, confirmations: usize) -> Self {
+        let genesis = Block::genesis(genesis_state);
+        let genesis_hash = hash(&genesis.header);
+        Self {
+            blocks: HashMap::from([(genesis_hash, genesis)]),
+            genesis_hash,
+            states: HashMap::from([(genesis_hash, genesis_state.clone())]),
+            executed_blocks: 0,
+            best_tip: genesis_hash,
+            confirmations,
+            finalized: HashSet::from([genesis_hash]),
+            last_finalized: genesis_hash,
+        }
+    }
+
+    /// The hash of the genesis block.
+    pub fn genesis_hash(&self) -> Hash {
+        self.genesis_hash
+    }
+
+    /// The imported block with the given header hash, if any.
+    pub fn block(&self, block_hash: Hash) -> Option<&Block> {
+        self.blocks.get(&block_hash)
+    }
+
+    /// The state after the imported block with the given header hash, if any.
+    pub fn state(&self, block_hash: Hash) -> Option<&State> {
+        self.states.get(&block_hash)
+    }
+
+    /// The hash of the tip of the best chain.
+    pub fn best_tip(&self) -> Hash {
+        self.best_tip
+    }
+
+    /// Whether the block with the given header hash is final. Once a block is final it stays
+    /// final, whatever is imported later. Unknown blocks are never final.
+    pub fn is_final(&self, block_hash: Hash) -> bool {
+        self.finalized.contains(&block_hash)
+    }
+
+    /// The tip of the chain with the most accumulated work, measured the same way as the
+    /// `HeaviestChainRule`. Every leaf of the block tree, meaning every block without children,
+    /// is a candidate unless it forks off below the last final block. If two leaves carry the
+    /// same work, the one with the lower hash wins.
+    ///
+    /// The store itself follows the longest chain, and finality is based on `best_tip` alone.
+    /// This reports where the heaviest chain rule would lead instead, so that the two rules can
+    /// be compared on the same blocks. Both respect finality, but they can disagree above it.
+    pub fn heaviest_tip(&self) -> Hash {
+        let parents: HashSet<Hash> = self.blocks.values().map(|block| block.header.parent).collect();
+        let last_finalized_height = self.blocks[&self.last_finalized].header.height;
+
+        self.blocks
+            .keys()
+            .filter(|block_hash| !parents.contains(*block_hash))
+            .filter(|block_hash| self.ancestor_at(**block_hash, last_finalized_height) == Some(self.last_finalized))
+            .map(|block_hash| (*block_hash, self.accumulated_work(*block_hash)))
+            .max_by(|(hash_1, work_1), (hash_2, work_2)| work_1.cmp(work_2).then(hash_2.cmp(hash_1)))
+            .map(|(block_hash, _)| block_hash)
+            .unwrap_or(self.last_finalized)
+    }
+
+    /// The work of the given block and all its ancestors down to genesis.
+    fn accumulated_work(&self, block_hash: Hash) -> i128 {
+        let mut work = 0;
+        let mut cursor = block_hash;
+        while let Some(block) = self.blocks.get(&cursor) {
+            work += ThresholdHeaviestRule::<THRESHOLD>::work_of_hash(cursor);
+            if cursor == self.genesis_hash {
+                break;
+            }
+            cursor = block.header.parent;
+        }
+        work
+    }
+
+    /// Walk back from the given block to its ancestor at the given height. The block itself
+    /// counts as its own ancestor. Returns `None` if the block is lower than the height.
+    fn ancestor_at(&self, block_hash: Hash, height: u64) -> Option<Hash> {
+        let mut cursor = block_hash;
+        loop {
+            let header = &self.blocks.get(&cursor)?.header;
+            if header.height == height {
+                return Some(cursor);
+            }
+            if header.height < height {
+                return None;
+            }
+            cursor = header.parent;
+        }
+    }
+
+    /// Make the newly imported block the best tip if it is longer than the current best chain
+    /// and doesn't fork off below the last final block, then finalize whatever is now buried
+    /// deep enough.
+    fn update_best_chain(&mut self, block_hash: Hash) {
+        let height = self.blocks[&block_hash].header.height;
+        let last_finalized_height = self.blocks[&self.last_finalized].header.height;
+        if height <= self.blocks[&self.best_tip].header.height
+            || self.ancestor_at(block_hash, last_finalized_height) != Some(self.last_finalized)
+        {
+            return;
+        }
+        self.best_tip = block_hash;
+
+        let Some(final_height) = finalized_height(height as usize + 1, self.confirmations) else {
+            return;
+        };
+        if final_height <= last_finalized_height {
+            return;
+        }
+        let new_last_finalized = self
+            .ancestor_at(block_hash, final_height)
+            .expect("the best tip is higher than the final height");
+        // The ancestors up to the previous last final block become final too.
+        let mut cursor = new_last_finalized;
+        while self.finalized.insert(cursor) {
+            cursor = self.blocks[&cursor].header.parent;
+        }
+        self.last_finalized = new_last_finalized;
+    }
+
+    /// Check the block and add it to the store. Returns the hash of its header on success.
+    ///
+    /// The proof of work is checked first, because it is the cheapest check and stops anyone
+    /// from filling the store with blocks that took no effort to make. Only then is the block
+    /// executed on top of the cached state of its parent, which is where it forks off if it
+    /// isn't on the same chain as the previous import.
+    pub fn import_block(&mut self, block: Block) -> Result<Hash, ImportError> {
+        let block_hash = hash(&block.header);
+        if block_hash >= THRESHOLD {
+            return Err(ImportError::InsufficientWork);
+        }
+        if self.blocks.contains_key(&block_hash) {
+            return Err(ImportError::DuplicateBlock);
+        }
+
+        let parent = self
+            .blocks
+            .get(&block.header.parent)
+            .ok_or(ImportError::UnknownParent(block.header.parent))?;
+        if parent.header.height.saturating_add(1) != block.header.height {
+            return Err(ImportError::HeightMismatch);
+        }
+        if hash(&block.body) != block.header.extrinsics_root {
+            return Err(ImportError::BadExtrinsicsRoot);
+        }
+
+        // Every imported block has its state cached, so the parent's state is always there.
+        let pre_state = &self.states[&block.header.parent];
+        let post_state = Block::execute_extrinsics_ref(pre_state, &block.body);
+        self.executed_blocks += 1;
+        if state_root(&post_state) != block.header.state_root {
+            return Err(ImportError::BadStateRoot);
+        }
+
+        self.states.insert(block_hash, post_state);
+        self.blocks.insert(block_hash, block);
+        self.update_best_chain(block_hash);
+        Ok(block_hash)
+    }
+}
+
+/// Search for a consensus digest that puts the hash of the block's header below the PoW threshold.
+pub fn mine(block: &mut Block) {
+    block.header.consensus_digest = 0;
+    while hash(&block.header) >= THRESHOLD {
+        block.header.consensus_digest += 1;
+    }
+}
+
+/// Build a child of the given block on top of its post-state, and mine it.
+#[cfg(test)]
+fn mined_child(parent: &Block, pre_state: &State, extrinsics: Vec<u64>) -> Block {
+    let mut block = parent.child(pre_state, extrinsics);
+    mine(&mut block);
+    block
+}
+
+/// Mine and import a chain of blocks on top of the given imported block, one block per
+/// extrinsic. Returns the hashes of the new blocks in order.
+#[cfg(test)]
+fn import_chain_on(chain: &mut Blockchain, parent_hash: Hash, extrinsics: &[u64]) -> Vec<Hash> {
+    let mut hashes = Vec::new();
+    let mut parent_hash = parent_hash;
+    for extrinsic in extrinsics {
+        let parent = chain.block(parent_hash).unwrap().clone();
+        let pre_state = chain.state(parent_hash).unwrap().clone();
+        parent_hash = chain.import_block(mined_child(&parent, &pre_state, vec![*extrinsic])).unwrap();
+        hashes.push(parent_hash);
+    }
+    hashes
+}
+
+/// Like `import_chain_on`, but mine every block below the given threshold rather than the
+/// usual `THRESHOLD`, so that the chain carries more work per block.
+#[cfg(test)]
+fn import_hard_chain_on(chain: &mut Blockchain, parent_hash: Hash, extrinsics: &[u64], threshold: u64) -> Vec<Hash> {
+    let mut hashes = Vec::new();
+    let mut parent_hash = parent_hash;
+    for extrinsic in extrinsics {
+        let parent = chain.block(parent_hash).unwrap().clone();
+        let pre_state = chain.state(parent_hash).unwrap().clone();
+        let mut block = parent.child(&pre_state, vec![*extrinsic]);
+        while hash(&block.header) >= threshold {
+            block.header.consensus_digest += 1;
+        }
+        parent_hash = chain.import_block(block).unwrap();
+        hashes.push(parent_hash);
+    }
+    hashes
+}
+
+#[cfg(test)]
+#[test]
+fn bc_8_import_mined_block() {
+    let genesis_state = State { sum: 0, product: 1 };
+    let mut chain = Blockchain::new(&genesis_state);
+    let genesis = chain.block(chain.genesis_hash()).unwrap().clone();
+
+    let b1 = mined_child(&genesis, &genesis_state, vec![1, 2]);
+    assert_eq!(chain.import_block(b1.clone()), Ok(hash(&b1.header)));
+    assert_eq!(chain.block(hash(&b1.header)), Some(&b1));
+}
+
+#[test]
+fn bc_8_unmined_block_is_rejected() {
+    let genesis_state = State { sum: 0, product: 1 };
+    let mut chain = Blockchain::new(&genesis_state);
+    let genesis = chain.block(chain.genesis_hash()).unwrap().clone();
+
+    let mut b1 = genesis.child(&genesis_state, vec![1, 2]);
+    while hash(&b1.header) < THRESHOLD {
+        b1.header.consensus_digest += 1;
+    }
+    assert_eq!(chain.import_block(b1.clone()), Err(ImportError::InsufficientWork));
+    assert_eq!(chain.block(hash(&b1.header)), None);
+}
+
+#[test]
+fn bc_8_block_with_unknown_parent_is_rejected() {
+    let genesis_state = State { sum: 0, product: 1 };
+    let mut chain = Blockchain::new(&genesis_state);
+    let genesis = chain.block(chain.genesis_hash()).unwrap().clone();
+
+    let b1 = mined_child(&genesis, &genesis_state, vec![1]);
+    let b2 = mined_child(&b1, &State { sum: 1, product: 1 }, vec![2]);
+    assert_eq!(chain.import_block(b2), Err(ImportError::UnknownParent(hash(&b1.header))));
+}
+
+#[test]
+fn bc_8_block_with_wrong_state_root_is_rejected() {
+    let genesis_state = State { sum: 0, product: 1 };
+    let mut chain = Blockchain::new(&genesis_state);
+    let genesis = chain.block(chain.genesis_hash()).unwrap().clone();
+
+    let mut b1 = genesis.child(&genesis_state, vec![1, 2]);
+    b1.header.state_root = state_root(&State { sum: 4, product: 2 });
+    mine(&mut b1);
+    assert_eq!(chain.import_block(b1), Err(ImportError::BadStateRoot));
+}
+
+#[test]
+fn bc_8_block_with_wrong_height_is_rejected() {
+    let genesis_state = State { sum: 0, product: 1 };
+    let mut chain = Blockchain::new(&genesis_state);
+    let genesis = chain.block(chain.genesis_hash()).unwrap().clone();
+
+    let mut b1 = genesis.child(&genesis_state, vec![1, 2]);
+    b1.header.height = 2;
+    mine(&mut b1);
+    assert_eq!(chain.import_block(b1), Err(ImportError::HeightMismatch));
+}
+
+#[test]
+fn bc_8_block_with_wrong_extrinsics_root_is_rejected() {
+    let genesis_state = State { sum: 0, product: 1 };
+    let mut chain = Blockchain::new(&genesis_state);
+    let genesis = chain.block(chain.genesis_hash()).unwrap().clone();
+
+    let mut b1 = genesis.child(&genesis_state, vec![1, 2]);
+    b1.body.push(3);
+    mine(&mut b1);
+    assert_eq!(chain.import_block(b1), Err(ImportError::BadExtrinsicsRoot));
+}
+
+#[test]
+fn bc_8_duplicate_block_is_rejected() {
+    let genesis_state = State { sum: 0, product: 1 };
+    let mut chain = Blockchain::new(&genesis_state);
+    let genesis = chain.block(chain.genesis_hash()).unwrap().clone();
+
+    let b1 = mined_child(&genesis, &genesis_state, vec![1, 2]);
+    chain.import_block(b1.clone()).unwrap();
+    assert_eq!(chain.import_block(b1), Err(ImportError::DuplicateBlock));
+    // The duplicate is caught before the block would be executed a second time.
+    assert_eq!(chain.executed_blocks, 1);
+}
+
+#[test]
+fn bc_8_fork_switch_reuses_cached_ancestor_state() {
+    let genesis_state = State { sum: 0, product: 1 };
+    let mut chain = Blockchain::new(&genesis_state);
+    let genesis = chain.block(chain.genesis_hash()).unwrap().clone();
+
+    // G -- 1 -- 2 -- 3
+    //       \-- 2'
+    let b1 = mined_child(&genesis, &genesis_state, vec![2]);
+    let s1 = Block::execute_extrinsics_ref(&genesis_state, &b1.body);
+    let b2 = mined_child(&b1, &s1, vec![3]);
+    let s2 = Block::execute_extrinsics_ref(&s1, &b2.body);
+    let b3 = mined_child(&b2, &s2, vec![4]);
+    for block in [b1.clone(), b2, b3] {
+        chain.import_block(block).unwrap();
+    }
+    assert_eq!(chain.executed_blocks, 3);
+
+    // Importing the fork only executes the new block, on top of the state cached for block 1.
+    let b2_prime = mined_child(&b1, &s1, vec![5]);
+    let b2_prime_hash = chain.import_block(b2_prime).unwrap();
+    assert_eq!(chain.executed_blocks, 4);
+    assert_eq!(chain.state(b2_prime_hash), Some(&State { sum: 7, product: 10 }));
+}
+
+#[test]
+fn bc_8_finalized_height() {
+    assert_eq!(finalized_height(0, 3), None);
+    assert_eq!(finalized_height(3, 3), None);
+    assert_eq!(finalized_height(4, 3), Some(0));
+    assert_eq!(finalized_height(10, 3), Some(6));
+    assert_eq!(finalized_height(10, 0), Some(9));
+}
+
+#[test]
+fn bc_8_deeply_buried_block_is_final() {
+    let mut chain = Blockchain::with_confirmations(&State { sum: 0, product: 1 }, 3);
+    let genesis_hash = chain.genesis_hash();
+    let hashes = import_chain_on(&mut chain, genesis_hash, &[1, 2, 3, 4, 5, 6]);
+
+    assert_eq!(chain.best_tip(), hashes[5]);
+    assert!(chain.is_final(genesis_hash));
+    // The tip is at height 6, so everything up to height 3 is buried under three blocks.
+    for hash in &hashes[..3] {
+        assert!(chain.is_final(*hash));
+    }
+    for hash in &hashes[3..] {
+        assert!(!chain.is_final(*hash));
+    }
+}
+
+#[test]
+fn bc_8_longer_fork_below_finality_does_not_unfinalize() {
+    let mut chain = Blockchain::with_confirmations(&State { sum: 0, product: 1 }, 2);
+    let genesis_hash = chain.genesis_hash();
+    let main = import_chain_on(&mut chain, genesis_hash, &[1, 2, 3, 4]);
+    assert!(chain.is_final(main[1]));
+
+    // A longer fork off genesis would replace the final blocks, so it never becomes best.
+    let fork = import_chain_on(&mut chain, genesis_hash, &[5, 6, 7, 8, 9, 10]);
+    assert_eq!(chain.best_tip(), main[3]);
+    assert!(chain.is_final(main[1]));
+    assert!(!chain.is_final(fork[1]));
+
+    // A longer fork above the last final block is still allowed to take over.
+    let late_fork = import_chain_on(&mut chain, main[1], &[11, 12, 13]);
+    assert_eq!(chain.best_tip(), late_fork[2]);
+    assert!(chain.is_final(late_fork[0]));
+    assert!(!chain.is_final(main[2]));
+}
+
+#[test]
+fn bc_8_heaviest_tip_of_fork() {
+    let genesis_state = State { sum: 0, product: 1 };
+    let mut chain = Blockchain::new(&genesis_state);
+    let genesis_hash = chain.genesis_hash();
+    assert_eq!(chain.heaviest_tip(), genesis_hash);
+
+    // G -- 1 -- 2
+    //  \-- 1'-- 2'-- 3'
+    let short = import_chain_on(&mut chain, genesis_hash, &[1, 2]);
+
+    // Mine the long fork four times harder, so that each of its blocks carries at least three
+    // quarters of the most work any block on the short fork can have.
+    let long = import_hard_chain_on(&mut chain, genesis_hash, &[3, 4, 5], THRESHOLD / 4);
+
+    assert!(chain.accumulated_work(long[2]) > chain.accumulated_work(short[1]));
+    assert_eq!(chain.heaviest_tip(), long[2]);
+}
+
+#[test]
+fn bc_8_heavier_fork_below_finality_is_not_heaviest_tip() {
+    let mut chain = Blockchain::with_confirmations(&State { sum: 0, product: 1 }, 1);
+    let genesis_hash = chain.genesis_hash();
+
+    // G -- 1 -- 2
+    //  \-- 1'-- 2'-- 3'
+    let main = import_chain_on(&mut chain, genesis_hash, &[1, 2]);
+    assert!(chain.is_final(main[0]));
+
+    // The fork is heavier, but taking it would revert the final block 1.
+    let fork = import_hard_chain_on(&mut chain, genesis_hash, &[3, 4, 5], THRESHOLD / 4);
+    assert!(chain.accumulated_work(fork[2]) > chain.accumulated_work(main[1]));
+    assert_eq!(chain.heaviest_tip(), main[1]);
+    assert_eq!(chain.best_tip(), main[1]);
+}
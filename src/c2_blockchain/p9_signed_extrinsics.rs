@@ -0,0 +1,186 @@
+//! So far, anybody could put any extrinsic into a block, and there was no notion of who submitted it.
+//! In real blockchains, every extrinsic is signed by the user who submits it, and block execution
+//! refuses any extrinsic whose signature doesn't check out.
+//!
+//! Here we connect the `User` type from our state machines to the blockchain. Real signatures
+//! need real cryptography, so we use a toy scheme instead: the signature is simply the hash of
+//! the signer and the value. It is trivially forgeable, but it is enough to show where signature
+//! checks live in block execution.
+
+use crate::c1_state_machine::User;
+use crate::hash;
+
+// We will use Rust's built-in hashing where the output type is u64. I'll make an alias
+// so that the code is slightly more readable.
+type Hash = u64;
+
+/// An extrinsic that carries the user who submitted it and their signature over its contents.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct SignedExtrinsic {
+    from: User,
+    value: u64,
+    sig: u64,
+}
+
+impl SignedExtrinsic {
+    /// Create and sign a new extrinsic on behalf of the given user.
+    pub fn new(from: User, value: u64) -> Self {
+        Self {
+            from,
+            value,
+            sig: hash(&(from, value)),
+        }
+    }
+
+    /// Check that the signature matches the signer and the value.
+    pub fn is_valid(&self) -> bool {
+        self.sig == hash(&(self.from, self.value))
+    }
+}
+
+/// The header is the same as in the batched extrinsics lesson. The extrinsics root now commits
+/// to the signed extrinsics, signatures included.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Header {
+    parent: Hash,
+    height: u64,
+    extrinsics_root: Hash,
+    state: u64,
+    consensus_digest: u64,
+}
+
+impl Header {
+    /// Returns a new valid genesis header.
+    pub fn genesis() -> Self {
+        Self {
+            parent: Hash::default(),
+            height: 0,
+            extrinsics_root: Hash::default(),
+            state: 0,
+            consensus_digest: 0,
+        }
+    }
+
+    /// Create and return a valid child header.
+    /// The height saturates at `u64::MAX` instead of overflowing.
+    pub fn child(&self, extrinsics_root: Hash, state: u64) -> Self {
+        Self {
+            parent: hash(self),
+            height: self.height.saturating_add(1),
+            extrinsics_root,
+            state,
+            consensus_digest: 0,
+        }
+    }
+}
+
+/// A complete block is a header and the signed extrinsics.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Block {
+    pub(crate) header: Header,
+    pub(crate) body: Vec<SignedExtrinsic>,
+}
+
+impl Block {
+    /// Returns a new valid genesis block. By convention, this block has no extrinsics.
+    pub fn genesis() -> Self {
+        Self {
+            header: Header::genesis(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Returns the sum of the extrinsic values, or `None` if any extrinsic carries a
+    /// signature that doesn't match its contents.
+    pub fn execute_extrinsics(extrinsics: &[SignedExtrinsic]) -> Option<u64> {
+        let mut state: u64 = 0;
+        for extrinsic in extrinsics {
+            if !extrinsic.is_valid() {
+                return None;
+            }
+            state = state.saturating_add(extrinsic.value);
+        }
+        Some(state)
+    }
+
+    /// Create and return a valid child block, or `None` if any of the extrinsics is forged.
+    pub fn child(&self, extrinsics: Vec<SignedExtrinsic>) -> Option<Self> {
+        let executed = Block::execute_extrinsics(&extrinsics)?;
+        Some(Self {
+            header: self
+                .header
+                .child(hash(&extrinsics), self.header.state.saturating_add(executed)),
+            body: extrinsics,
+        })
+    }
+
+    /// Verify that all the given blocks form a valid chain from this block to the tip.
+    /// On top of the usual header and state checks, every extrinsic must be properly signed.
+    pub fn verify_sub_chain(&self, chain: &[Block]) -> bool {
+        let mut prev_block = self;
+        for curr_block in chain {
+            let executed = match Block::execute_extrinsics(&curr_block.body) {
+                Some(executed) => executed,
+                None => return false,
+            };
+            if prev_block.header.height.saturating_add(1) != curr_block.header.height
+                || curr_block.header.parent != hash(&prev_block.header)
+                || curr_block.header.extrinsics_root != hash(&curr_block.body)
+                || curr_block.header.state != prev_block.header.state.saturating_add(executed)
+            {
+                return false;
+            }
+            prev_block = curr_block;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn bc_9_signed_extrinsic_is_valid() {
+    let extrinsic = SignedExtrinsic::new(User::Alice, 10);
+    assert!(extrinsic.is_valid());
+}
+
+#[test]
+fn bc_9_accepts_signed_extrinsics() {
+    let g = Block::genesis();
+    let b1 = g
+        .child(vec![
+            SignedExtrinsic::new(User::Alice, 10),
+            SignedExtrinsic::new(User::Bob, 5),
+        ])
+        .unwrap();
+    let b2 = b1.child(vec![SignedExtrinsic::new(User::Charlie, 7)]).unwrap();
+
+    assert_eq!(b2.header.state, 22);
+    assert!(g.verify_sub_chain(&[b1, b2]));
+}
+
+#[test]
+fn bc_9_rejects_forged_extrinsic() {
+    // Bob claims the value Alice signed for.
+    let mut forged = SignedExtrinsic::new(User::Alice, 10);
+    forged.from = User::Bob;
+    assert!(!forged.is_valid());
+
+    let g = Block::genesis();
+    assert_eq!(g.child(vec![forged.clone()]), None);
+
+    // Sneaking the forged extrinsic into an otherwise well-formed block doesn't work either.
+    let mut b1 = g.child(vec![SignedExtrinsic::new(User::Alice, 10)]).unwrap();
+    b1.body = vec![forged];
+    b1.header.extrinsics_root = hash(&b1.body);
+
+    assert!(!g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_9_empty_chain_is_valid() {
+    let g = Block::genesis();
+    let b1 = g.child(vec![SignedExtrinsic::new(User::Alice, 10)]).unwrap();
+
+    assert!(g.verify_sub_chain(&[]));
+    assert!(b1.verify_sub_chain(&[]));
+}
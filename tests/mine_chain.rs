@@ -0,0 +1,29 @@
+#[allow(dead_code)]
+#[path = "../examples/mine_chain.rs"]
+mod mine_chain;
+
+use mine_chain::{describe, mine_chain, parse_batch, THRESHOLD};
+use Blockchain_from_scratch::hash;
+
+#[test]
+fn example_mines_chain_to_final_state() {
+    let batches: Vec<Vec<u64>> = ["1,2,3", "4", ""]
+        .iter()
+        .map(|arg| parse_batch(arg).unwrap())
+        .collect();
+    let chain = mine_chain(&batches, THRESHOLD);
+
+    assert_eq!(chain.len(), 4);
+    assert_eq!(chain.last().unwrap().header().state(), 10);
+    assert!(chain[0].verify_sub_chain(&chain[1..]));
+    for block in &chain[1..] {
+        assert!(hash(block.header()) < THRESHOLD);
+    }
+    assert!(describe(&chain[3]).starts_with("height:   3"));
+    assert!(describe(&chain[3]).ends_with("state: 10"));
+}
+
+#[test]
+fn example_rejects_bad_extrinsic() {
+    assert!(parse_batch("1,two").is_err());
+}